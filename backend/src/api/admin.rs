@@ -0,0 +1,104 @@
+use super::api_keys::{self, ApiKey, CreatedKey, Scope};
+use super::{AdminToken, Installed};
+use crate::common::error::{AppResult, CustomResult};
+use crate::common::i18n::Locale;
+use crate::storage::sql::introspect::{self, DriftReport};
+use crate::AppState;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, State};
+use serde::Deserialize;
+use std::sync::Arc;
+
+// 对比实际数据库结构与迁移定义的结构，只读
+#[get("/schema/drift")]
+pub async fn schema_drift_handler(
+    _stage: Installed,
+    _token: AdminToken,
+    state: &State<Arc<AppState>>,
+) -> AppResult<Json<DriftReport>> {
+    let sql = state.sql_get().await?;
+    let report = introspect::detect_drift(&sql).await?;
+    Ok(Json(report))
+}
+
+#[get("/keys")]
+pub async fn list_api_keys_handler(
+    _stage: Installed,
+    _token: AdminToken,
+    state: &State<Arc<AppState>>,
+) -> AppResult<Json<Vec<ApiKey>>> {
+    let sql = state.sql_get().await?;
+    let keys = api_keys::list_keys(&sql).await?;
+    Ok(Json(keys))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateApiKeyData {
+    name: String,
+    scopes: Vec<String>,
+    // 不填表示永不过期
+    expires_in_days: Option<i64>,
+}
+
+#[post("/keys", format = "application/json", data = "<data>")]
+pub async fn create_api_key_handler(
+    _stage: Installed,
+    token: AdminToken,
+    state: &State<Arc<AppState>>,
+    data: Json<CreateApiKeyData>,
+) -> AppResult<Json<CreatedKey>> {
+    let scopes = data
+        .scopes
+        .iter()
+        .map(|scope| Scope::from_str(scope))
+        .collect::<CustomResult<Vec<_>>>()?;
+    let expires_at = api_keys::expires_at(data.expires_in_days)?;
+
+    let sql = state.sql_get().await?;
+    let created = api_keys::create_key(&sql, &data.name, &scopes, expires_at, &token.0)
+        .await?;
+    Ok(Json(created))
+}
+
+#[delete("/keys/<id>")]
+pub async fn revoke_api_key_handler(
+    _stage: Installed,
+    _token: AdminToken,
+    state: &State<Arc<AppState>>,
+    locale: Locale,
+    id: &str,
+) -> AppResult<String> {
+    let sql = state.sql_get().await?;
+    api_keys::revoke_key(&sql, id).await?;
+    Ok(locale.format("api_key.revoked", &[("id", id.to_string())]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::testing;
+    use rocket::http::{ContentType, Header, Status};
+
+    #[tokio::test]
+    async fn key_validity_is_checked_for_overflow() {
+        let site = testing::site(|_| {}).await;
+        for (days, expected) in [
+            (i64::MAX, Status::BadRequest),
+            (1_000_000_000, Status::BadRequest),
+            (0, Status::BadRequest),
+            (30, Status::Ok),
+        ] {
+            let response = site
+                .client
+                .post("/admin/keys")
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", site.bearer("alice")))
+                .body(
+                    serde_json::json!({ "name": "deploy", "scopes": ["fields:read"], "expires_in_days": days })
+                        .to_string(),
+                )
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), expected, "expires_in_days = {}", days);
+        }
+    }
+}
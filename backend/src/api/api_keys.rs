@@ -0,0 +1,280 @@
+use crate::common::error::{CustomError, CustomResult, ErrorKind};
+use crate::common::helpers;
+use crate::storage::sql::{
+    self,
+    builder::{self, Condition, Operator, SafeValue, SqlOperation, ValidationLevel, WhereClause},
+};
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+// 完整密钥格式为 ek_<id>_<secret>，id 明文存储用于查找，secret 只存储哈希
+pub const KEY_PREFIX: &str = "ek_";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    FieldsRead,
+    FieldsWrite,
+    PostsRead,
+    PostsWrite,
+    PagesRead,
+    PagesWrite,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Scope::FieldsRead => write!(f, "fields:read"),
+            Scope::FieldsWrite => write!(f, "fields:write"),
+            Scope::PostsRead => write!(f, "posts:read"),
+            Scope::PostsWrite => write!(f, "posts:write"),
+            Scope::PagesRead => write!(f, "pages:read"),
+            Scope::PagesWrite => write!(f, "pages:write"),
+        }
+    }
+}
+
+impl Scope {
+    pub fn from_str(s: &str) -> CustomResult<Self> {
+        match s.to_lowercase().as_str() {
+            "fields:read" => Ok(Scope::FieldsRead),
+            "fields:write" => Ok(Scope::FieldsWrite),
+            "posts:read" => Ok(Scope::PostsRead),
+            "posts:write" => Ok(Scope::PostsWrite),
+            "pages:read" => Ok(Scope::PagesRead),
+            "pages:write" => Ok(Scope::PagesWrite),
+            _ => Err(CustomError::localized(ErrorKind::Validation, "api_key.invalid_scope").with_arg("scope", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_by: String,
+    // 以下均为 Unix 秒，0 表示未使用、永不过期或未吊销
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub expires_at: i64,
+    pub revoked_at: i64,
+}
+
+impl ApiKey {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| s == &scope.to_string())
+    }
+
+    fn from_row(row: &HashMap<String, Value>) -> Self {
+        let text = |key: &str| {
+            row.get(key)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let number = |key: &str| row.get(key).and_then(|value| value.as_i64()).unwrap_or(0);
+        ApiKey {
+            id: text("id"),
+            name: text("name"),
+            scopes: text("scopes").split_whitespace().map(String::from).collect(),
+            created_by: text("created_by"),
+            created_at: number("created_at"),
+            last_used_at: number("last_used_at"),
+            expires_at: number("expires_at"),
+            revoked_at: number("revoked_at"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedKey {
+    pub id: String,
+    pub key: String,
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn id_condition(id: &str) -> CustomResult<WhereClause> {
+    Ok(WhereClause::Condition(Condition::new(
+        "id".to_string(),
+        Operator::Eq,
+        Some(SafeValue::Text(id.to_string(), ValidationLevel::Strict)),
+    )?))
+}
+
+// 有效天数换算为过期时间，不填表示永不过期（0）
+pub fn expires_at(days: Option<i64>) -> CustomResult<i64> {
+    match days {
+        Some(days) if days > 0 => Duration::try_days(days)
+            .and_then(|duration| Utc::now().checked_add_signed(duration))
+            .map(|time| time.timestamp())
+            .ok_or_else(|| CustomError::localized(ErrorKind::Validation, "api_key.too_many_days")),
+        Some(_) => Err(CustomError::localized(ErrorKind::Validation, "api_key.invalid_days")),
+        None => Ok(0),
+    }
+}
+
+// 完整密钥只在创建时返回一次
+pub async fn create_key(
+    sql: &sql::Database,
+    name: &str,
+    scopes: &[Scope],
+    expires_at: i64,
+    created_by: &str,
+) -> CustomResult<CreatedKey> {
+    if name.trim().is_empty() {
+        return Err(CustomError::localized(ErrorKind::Validation, "api_key.name_required"));
+    }
+    if scopes.is_empty() {
+        return Err(CustomError::localized(ErrorKind::Validation, "api_key.scope_required"));
+    }
+
+    let id = helpers::generate_random_string(12);
+    let secret = helpers::generate_random_string(32);
+    let scopes = scopes
+        .iter()
+        .map(|scope| scope.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut builder =
+        builder::QueryBuilder::new(SqlOperation::Insert, sql.table_name("api_keys"), sql.get_type())?;
+    builder
+        .set_value(
+            "id".to_string(),
+            SafeValue::Text(id.clone(), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "name".to_string(),
+            SafeValue::Text(name.trim().to_string(), ValidationLevel::Standard),
+        )?
+        .set_value(
+            "key_hash".to_string(),
+            SafeValue::Text(hash_secret(&secret), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "scopes".to_string(),
+            SafeValue::Text(scopes, ValidationLevel::Standard),
+        )?
+        .set_value(
+            "created_by".to_string(),
+            SafeValue::Text(created_by.to_string(), ValidationLevel::Standard),
+        )?
+        .set_value(
+            "created_at".to_string(),
+            SafeValue::Integer(Utc::now().timestamp()),
+        )?
+        .set_value("last_used_at".to_string(), SafeValue::Integer(0))?
+        .set_value("expires_at".to_string(), SafeValue::Integer(expires_at))?
+        .set_value("revoked_at".to_string(), SafeValue::Integer(0))?;
+    sql.execute_query(&builder).await?;
+
+    Ok(CreatedKey {
+        key: format!("{}{}_{}", KEY_PREFIX, id, secret),
+        id,
+    })
+}
+
+pub async fn list_keys(sql: &sql::Database) -> CustomResult<Vec<ApiKey>> {
+    let builder =
+        builder::QueryBuilder::new(SqlOperation::Select, sql.table_name("api_keys"), sql.get_type())?;
+    let mut keys = sql
+        .execute_query(&builder)
+        .await?
+        .iter()
+        .map(ApiKey::from_row)
+        .collect::<Vec<_>>();
+    keys.sort_by_key(|key| key.created_at);
+    Ok(keys)
+}
+
+async fn find_key(sql: &sql::Database, id: &str) -> CustomResult<Option<(ApiKey, String)>> {
+    let mut builder =
+        builder::QueryBuilder::new(SqlOperation::Select, sql.table_name("api_keys"), sql.get_type())?;
+    builder.add_condition(id_condition(id)?);
+    // 吊销需要立即生效，不读只读副本
+    Ok(sql
+        .primary()
+        .execute_query(&builder)
+        .await?
+        .first()
+        .map(|row| {
+            let hash = row
+                .get("key_hash")
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string();
+            (ApiKey::from_row(row), hash)
+        }))
+}
+
+// 吊销后保留记录以便审计
+pub async fn revoke_key(sql: &sql::Database, id: &str) -> CustomResult<()> {
+    match find_key(sql, id).await? {
+        None => return Err(CustomError::localized(ErrorKind::NotFound, "api_key.not_found").with_arg("id", id)),
+        Some((key, _)) if key.revoked_at != 0 => {
+            return Err(CustomError::localized(ErrorKind::Conflict, "api_key.already_revoked").with_arg("id", id))
+        }
+        Some(_) => {}
+    }
+
+    let mut builder =
+        builder::QueryBuilder::new(SqlOperation::Update, sql.table_name("api_keys"), sql.get_type())?;
+    builder
+        .set_value(
+            "revoked_at".to_string(),
+            SafeValue::Integer(Utc::now().timestamp()),
+        )?
+        .add_condition(id_condition(id)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+pub async fn delete_key(sql: &sql::Database, id: &str) -> CustomResult<()> {
+    let mut builder =
+        builder::QueryBuilder::new(SqlOperation::Delete, sql.table_name("api_keys"), sql.get_type())?;
+    builder.add_condition(id_condition(id)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+pub async fn authenticate(sql: &sql::Database, key: &str) -> CustomResult<ApiKey> {
+    let (id, secret) = key
+        .strip_prefix(KEY_PREFIX)
+        .and_then(|rest| rest.split_once('_'))
+        .filter(|(id, _)| id.chars().all(|c| c.is_ascii_alphanumeric()))
+        .ok_or_else(|| CustomError::localized(ErrorKind::Unauthorized, "api_key.malformed"))?;
+
+    let (api_key, hash) = find_key(sql, id)
+        .await?
+        .ok_or_else(|| CustomError::localized(ErrorKind::Unauthorized, "api_key.invalid"))?;
+    if !helpers::constant_time_eq(hash.as_bytes(), hash_secret(secret).as_bytes()) {
+        return Err(CustomError::localized(ErrorKind::Unauthorized, "api_key.invalid"));
+    }
+    if api_key.revoked_at != 0 {
+        return Err(CustomError::localized(ErrorKind::Unauthorized, "api_key.key_revoked"));
+    }
+    if api_key.expires_at != 0 && api_key.expires_at <= Utc::now().timestamp() {
+        return Err(CustomError::localized(ErrorKind::Unauthorized, "api_key.expired"));
+    }
+    Ok(api_key)
+}
+
+pub async fn touch(sql: &sql::Database, id: &str) -> CustomResult<()> {
+    let mut builder =
+        builder::QueryBuilder::new(SqlOperation::Update, sql.table_name("api_keys"), sql.get_type())?;
+    builder
+        .set_value(
+            "last_used_at".to_string(),
+            SafeValue::Integer(Utc::now().timestamp()),
+        )?
+        .add_condition(id_condition(id)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
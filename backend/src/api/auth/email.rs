@@ -0,0 +1,510 @@
+use super::throttle::{self, LoginThrottle};
+use crate::api::{users, Installed, Principal};
+use crate::common::config;
+use crate::common::error::{AppResult, CustomError, CustomErrorInto, CustomResult, ErrorKind};
+use crate::common::i18n::Locale;
+use crate::common::helpers;
+use crate::mail::Mailer;
+use crate::security::password;
+use crate::storage::sql::{
+    self,
+    builder::{self, Condition, Operator, SafeValue, SqlOperation, ValidationLevel, WhereClause},
+};
+use crate::AppState;
+use chrono::Utc;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{post, State};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+// 同一用户同一用途的邮件至少间隔该秒数
+const RESEND_INTERVAL: i64 = 60;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Purpose {
+    PasswordReset,
+    EmailVerify,
+}
+
+impl Display for Purpose {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Purpose::PasswordReset => write!(f, "password_reset"),
+            Purpose::EmailVerify => write!(f, "email_verify"),
+        }
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn text_condition(field: &str, value: &str) -> CustomResult<WhereClause> {
+    Ok(WhereClause::Condition(Condition::new(
+        field.to_string(),
+        Operator::Eq,
+        Some(SafeValue::Text(value.to_string(), ValidationLevel::Standard)),
+    )?))
+}
+
+fn unused_condition(username: &str, purpose: Purpose) -> CustomResult<WhereClause> {
+    Ok(WhereClause::And(vec![
+        text_condition("username", username)?,
+        text_condition("purpose", &purpose.to_string())?,
+        WhereClause::Condition(Condition::new(
+            "used_at".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Integer(0)),
+        )?),
+    ]))
+}
+
+async fn recently_issued(sql: &sql::Database, username: &str, purpose: Purpose) -> CustomResult<bool> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Select,
+        sql.table_name("user_tokens"),
+        sql.get_type(),
+    )?;
+    builder
+        .add_field("created_at".to_string())?
+        .add_condition(unused_condition(username, purpose)?);
+    let since = Utc::now().timestamp() - RESEND_INTERVAL;
+    Ok(sql
+        .primary()
+        .execute_query(&builder)
+        .await?
+        .iter()
+        .filter_map(|row| row.get("created_at").and_then(|value| value.as_i64()))
+        .any(|created_at| created_at > since))
+}
+
+// 签发新令牌前作废同一用途未使用的旧令牌，明文只出现在邮件中
+async fn issue(sql: &sql::Database, username: &str, purpose: Purpose, ttl: u64) -> CustomResult<String> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Delete,
+        sql.table_name("user_tokens"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(unused_condition(username, purpose)?);
+    sql.execute_query(&builder).await?;
+
+    let token = helpers::generate_random_string(32);
+    let now = Utc::now().timestamp();
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Insert,
+        sql.table_name("user_tokens"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value(
+            "token_hash".to_string(),
+            SafeValue::Text(hash_token(&token), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "username".to_string(),
+            SafeValue::Text(username.to_string(), ValidationLevel::Standard),
+        )?
+        .set_value(
+            "purpose".to_string(),
+            SafeValue::Text(purpose.to_string(), ValidationLevel::Strict),
+        )?
+        .set_value("created_at".to_string(), SafeValue::Integer(now))?
+        .set_value(
+            "expires_at".to_string(),
+            SafeValue::Integer(now + ttl as i64),
+        )?
+        .set_value("used_at".to_string(), SafeValue::Integer(0))?;
+    sql.execute_query(&builder).await?;
+    Ok(token)
+}
+
+// 校验并作废令牌，返回对应的用户名；校验和作废在同一条条件更新中完成，同一令牌只能使用一次
+async fn consume(sql: &sql::Database, token: &str, purpose: Purpose) -> CustomResult<Option<String>> {
+    if token.is_empty() || !token.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Ok(None);
+    }
+    let hash = hash_token(token);
+    let now = Utc::now().timestamp();
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Update,
+        sql.table_name("user_tokens"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value("used_at".to_string(), SafeValue::Integer(now))?
+        .add_condition(WhereClause::And(vec![
+            text_condition("token_hash", &hash)?,
+            text_condition("purpose", &purpose.to_string())?,
+            WhereClause::Condition(Condition::new(
+                "used_at".to_string(),
+                Operator::Eq,
+                Some(SafeValue::Integer(0)),
+            )?),
+            WhereClause::Condition(Condition::new(
+                "expires_at".to_string(),
+                Operator::Gt,
+                Some(SafeValue::Integer(now)),
+            )?),
+        ]));
+    if sql.execute_affected(&builder).await? != 1 {
+        return Ok(None);
+    }
+
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Select,
+        sql.table_name("user_tokens"),
+        sql.get_type(),
+    )?;
+    builder
+        .add_field("username".to_string())?
+        .add_condition(text_condition("token_hash", &hash)?);
+    Ok(sql
+        .primary()
+        .execute_query(&builder)
+        .await?
+        .first()
+        .and_then(|row| row.get("username"))
+        .and_then(|value| value.as_str())
+        .map(String::from))
+}
+
+// 配置了站点地址时附带完整链接，否则只给出令牌
+fn link(locale: Locale, site_url: &str, path: &str, token: &str) -> String {
+    if site_url.is_empty() {
+        return locale.format("email.token_only", &[("token", token.to_string())]);
+    }
+    format!("{}/{}?token={}", site_url.trim_end_matches('/'), path, token)
+}
+
+fn mailer() -> AppResult<(Mailer, config::MailConfig)> {
+    let config = config::Config::current().mail.clone();
+    let mailer = Mailer::new(&config)?;
+    Ok((mailer, config))
+}
+
+async fn send_reset(
+    sql: &sql::Database,
+    mailer: &Mailer,
+    config: &config::MailConfig,
+    locale: Locale,
+    email: &str,
+) -> CustomResult<()> {
+    let Some((username, email, _)) = users::contact_by_email(sql, email).await? else {
+        return Ok(());
+    };
+    if recently_issued(sql, &username, Purpose::PasswordReset).await? {
+        return Ok(());
+    }
+    let token = issue(sql, &username, Purpose::PasswordReset, config.reset_ttl).await?;
+    mailer
+        .send(
+            &email,
+            &locale.text("email.reset_subject"),
+            &locale.format(
+                "email.reset_body",
+                &[
+                    ("username", username.clone()),
+                    ("minutes", (config.reset_ttl / 60).to_string()),
+                    ("link", link(locale, &config.site_url, "reset-password", &token)),
+                ],
+            ),
+        )
+        .await
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForgotData {
+    email: String,
+}
+
+// 无论邮箱是否存在都返回相同结果，邮件在后台发送，避免通过响应判断账户是否存在
+#[post("/forgot", format = "application/json", data = "<data>")]
+pub async fn forgot_handler(
+    _stage: Installed,
+    throttle: LoginThrottle,
+    state: &State<Arc<AppState>>,
+    locale: Locale,
+    data: Json<ForgotData>,
+) -> AppResult<status::Accepted<String>> {
+    let (mailer, config) = mailer()?;
+    let email = data.email.trim().to_string();
+    throttle.mailed(&email).await?;
+    let sql = state.sql_get().await?;
+    state
+        .spawn_job(async move {
+            if let Err(e) = send_reset(&sql, &mailer, &config, locale, &email).await {
+                eprintln!("重置密码邮件发送失败: {}", e);
+            }
+        })
+        .await;
+    Ok(status::Accepted(locale.text("email.reset_requested")))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResetData {
+    token: String,
+    password: String,
+}
+
+#[post("/reset", format = "application/json", data = "<data>")]
+pub async fn reset_handler(
+    _stage: Installed,
+    state: &State<Arc<AppState>>,
+    locale: Locale,
+    data: Json<ResetData>,
+) -> AppResult<String> {
+    // 先检查密码规则，避免不合规的密码作废令牌
+    password::check_policy(&data.password)?;
+    let sql = state.sql_get().await?;
+    let Some(username) = consume(&sql, &data.token, Purpose::PasswordReset)
+        .await?
+    else {
+        return Err(CustomError::localized(ErrorKind::Validation, "email.invalid_token"));
+    };
+    users::set_password(&sql, &username, &data.password)
+        .await?;
+    throttle::clear_user(&sql, &username)
+        .await?;
+    Ok(locale.text("email.password_reset"))
+}
+
+// 向当前用户的邮箱发送验证邮件
+#[post("/verify/send")]
+pub async fn send_verification_handler(
+    _stage: Installed,
+    principal: Principal,
+    state: &State<Arc<AppState>>,
+    locale: Locale,
+) -> AppResult<status::Accepted<String>> {
+    let Principal::User(claims) = principal else {
+        return Err(CustomError::localized(ErrorKind::Forbidden, "email.api_key_forbidden"));
+    };
+    let sql = state.sql_get().await?;
+    let Some((username, email, verified_at)) = users::contact_by_username(&sql, &claims.name)
+        .await?
+    else {
+        return Err(CustomError::localized(ErrorKind::NotFound, "user.not_found"));
+    };
+    if verified_at != 0 {
+        return Err(CustomError::localized(ErrorKind::Conflict, "email.already_verified"));
+    }
+    let (mailer, config) = mailer()?;
+    if recently_issued(&sql, &username, Purpose::EmailVerify)
+        .await?
+    {
+        return Err(CustomError::localized(ErrorKind::TooManyRequests, "email.retry_later").with_arg("seconds", RESEND_INTERVAL));
+    }
+
+    let token = issue(&sql, &username, Purpose::EmailVerify, config.verify_ttl)
+        .await?;
+    mailer
+        .send(
+            &email,
+            &locale.text("email.verify_subject"),
+            &locale.format(
+                "email.verify_body",
+                &[
+                    ("username", username.clone()),
+                    ("hours", (config.verify_ttl / 3600).to_string()),
+                    ("link", link(locale, &config.site_url, "verify-email", &token)),
+                ],
+            ),
+        )
+        .await
+        .map_err(|e| format!("邮件发送失败: {}", e).into_error(ErrorKind::Upstream))?;
+    Ok(status::Accepted(locale.text("email.verification_sent")))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VerifyData {
+    token: String,
+}
+
+#[post("/verify", format = "application/json", data = "<data>")]
+pub async fn verify_handler(
+    _stage: Installed,
+    state: &State<Arc<AppState>>,
+    locale: Locale,
+    data: Json<VerifyData>,
+) -> AppResult<String> {
+    let sql = state.sql_get().await?;
+    let Some(username) = consume(&sql, &data.token, Purpose::EmailVerify)
+        .await?
+    else {
+        return Err(CustomError::localized(ErrorKind::Validation, "email.invalid_token"));
+    };
+    users::set_email_verified(&sql, &username, Utc::now().timestamp())
+        .await?;
+    Ok(locale.text("email.verified"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing::{self, TestSite};
+    use crate::api::users::Role;
+    use rocket::http::{ContentType, Header, Status};
+
+    async fn site() -> TestSite {
+        let site = testing::site(|config| {
+            config.mail.site_url = "https://example.com".to_string();
+        })
+        .await;
+        site.add_user("alice", "alice@example.com", Role::Administrator)
+            .await;
+        site
+    }
+
+    fn token_in(mail: &serde_json::Value) -> String {
+        let body = mail["body"].as_str().unwrap();
+        let start = body.find("token=").unwrap() + "token=".len();
+        body[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect()
+    }
+
+    async fn post(site: &TestSite, path: &str, body: serde_json::Value) -> Status {
+        site.client
+            .post(path)
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn forgot_then_reset_password() {
+        let site = site().await;
+        let forgot = serde_json::json!({ "email": "alice@example.com" });
+        assert_eq!(
+            post(&site, "/auth/password/forgot", forgot).await,
+            Status::Accepted
+        );
+        // 未注册的邮箱返回相同结果，且不发送邮件
+        let unknown = serde_json::json!({ "email": "nobody@example.com" });
+        assert_eq!(
+            post(&site, "/auth/password/forgot", unknown).await,
+            Status::Accepted
+        );
+
+        let mails = site.mails(1).await;
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0]["to"], "alice@example.com");
+        let token = token_in(&mails[0]);
+
+        let reset = serde_json::json!({ "token": token, "password": "another-strong-pass" });
+        assert_eq!(
+            post(&site, "/auth/password/reset", reset.clone()).await,
+            Status::Ok
+        );
+        assert_eq!(
+            post(&site, "/auth/password/reset", reset).await,
+            Status::BadRequest
+        );
+
+        let login = site
+            .client
+            .post("/auth/token/system")
+            .header(ContentType::JSON)
+            .body(
+                serde_json::json!({ "username": "alice", "password": "another-strong-pass" })
+                    .to_string(),
+            )
+            .dispatch()
+            .await;
+        assert_eq!(login.status(), Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn forgot_is_limited_per_email_and_ip() {
+        let site = site().await;
+        let forgot = |remote: &'static str, email: &'static str| {
+            site.client
+                .post("/auth/password/forgot")
+                .remote(remote.parse().unwrap())
+                .header(ContentType::JSON)
+                .body(serde_json::json!({ "email": email }).to_string())
+                .dispatch()
+        };
+        assert_eq!(
+            forgot("10.0.0.1:1", "alice@example.com").await.status(),
+            Status::Accepted
+        );
+        assert_eq!(
+            forgot("10.0.0.2:1", "Alice@example.com").await.status(),
+            Status::TooManyRequests
+        );
+        assert_eq!(
+            forgot("10.0.0.1:1", "nobody@example.com").await.status(),
+            Status::TooManyRequests
+        );
+        assert_eq!(site.mails(1).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn verify_email() {
+        let site = site().await;
+        let send = site
+            .client
+            .post("/auth/email/verify/send")
+            .header(Header::new("Authorization", site.bearer("alice")))
+            .dispatch()
+            .await;
+        assert_eq!(send.status(), Status::Accepted);
+        let token = token_in(&site.mails(1).await[0]);
+
+        // 验证令牌不能用于重置密码
+        let reset = serde_json::json!({ "token": token, "password": "another-strong-pass" });
+        assert_eq!(
+            post(&site, "/auth/password/reset", reset).await,
+            Status::BadRequest
+        );
+
+        let verify = serde_json::json!({ "token": token });
+        assert_eq!(
+            post(&site, "/auth/email/verify", verify.clone()).await,
+            Status::Ok
+        );
+        assert_eq!(
+            post(&site, "/auth/email/verify", verify).await,
+            Status::BadRequest
+        );
+        let (_, _, verified_at) = users::contact_by_username(&site.sql, "alice")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(verified_at, 0);
+    }
+
+    #[tokio::test]
+    async fn expired_and_concurrent_tokens_are_rejected() {
+        let site = site().await;
+        let expired = issue(&site.sql, "alice", Purpose::PasswordReset, 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            consume(&site.sql, &expired, Purpose::PasswordReset)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let token = issue(&site.sql, "alice", Purpose::PasswordReset, 600)
+            .await
+            .unwrap();
+        let (first, second) = tokio::join!(
+            consume(&site.sql, &token, Purpose::PasswordReset),
+            consume(&site.sql, &token, Purpose::PasswordReset)
+        );
+        let accepted = [first.unwrap(), second.unwrap()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        assert_eq!(accepted, vec!["alice".to_string()]);
+    }
+}
@@ -0,0 +1,475 @@
+use super::throttle::LoginThrottle;
+use super::token::{issue_token, LoginReply};
+use crate::api::{users, Installed, Principal};
+use crate::common::error::{AppResult, CustomError, CustomResult, ErrorKind};
+use crate::common::i18n::Locale;
+use crate::security::{jwt, totp};
+use crate::storage::sql::{
+    self,
+    builder::{self, Condition, Operator, SafeValue, SqlOperation, ValidationLevel, WhereClause},
+};
+use crate::AppState;
+use chrono::{Duration, Utc};
+use rocket::serde::json::Json;
+use rocket::{post, State};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// 密码验证通过、等待输入验证码时签发的临时令牌角色，不能用于访问其他接口
+pub const MFA_PENDING_ROLE: &str = "mfa_pending";
+
+struct UserTotp {
+    secret: String,
+    confirmed_at: i64,
+    last_step: i64,
+    recovery_codes: Vec<String>,
+}
+
+fn username_condition(username: &str) -> CustomResult<WhereClause> {
+    Ok(WhereClause::Condition(Condition::new(
+        "username".to_string(),
+        Operator::Eq,
+        Some(SafeValue::Text(
+            username.to_string(),
+            ValidationLevel::Standard,
+        )),
+    )?))
+}
+
+async fn find(sql: &sql::Database, username: &str) -> CustomResult<Option<UserTotp>> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Select,
+        sql.table_name("user_totp"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(username_condition(username)?);
+    Ok(sql
+        .primary()
+        .execute_query(&builder)
+        .await?
+        .first()
+        .map(|row| {
+            let text = |key: &str| {
+                row.get(key)
+                    .and_then(|value| value.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let number = |key: &str| row.get(key).and_then(|value| value.as_i64()).unwrap_or(0);
+            UserTotp {
+                secret: text("secret"),
+                confirmed_at: number("confirmed_at"),
+                last_step: number("last_step"),
+                recovery_codes: text("recovery_codes")
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect(),
+            }
+        }))
+}
+
+async fn save(sql: &sql::Database, username: &str, totp: &UserTotp, exists: bool) -> CustomResult<()> {
+    let operation = if exists {
+        SqlOperation::Update
+    } else {
+        SqlOperation::Insert
+    };
+    let mut builder =
+        builder::QueryBuilder::new(operation, sql.table_name("user_totp"), sql.get_type())?;
+    builder
+        .set_value(
+            "secret".to_string(),
+            SafeValue::Text(totp.secret.clone(), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "confirmed_at".to_string(),
+            SafeValue::Integer(totp.confirmed_at),
+        )?
+        .set_value("last_step".to_string(), SafeValue::Integer(totp.last_step))?
+        .set_value(
+            "recovery_codes".to_string(),
+            SafeValue::Text(totp.recovery_codes.join(" "), ValidationLevel::Standard),
+        )?;
+    if exists {
+        builder.add_condition(username_condition(username)?);
+    } else {
+        builder.set_value(
+            "username".to_string(),
+            SafeValue::Text(username.to_string(), ValidationLevel::Standard),
+        )?;
+    }
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+// 关闭两步验证，供用户本人和命令行找回账户使用
+pub async fn disable(sql: &sql::Database, username: &str) -> CustomResult<()> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Delete,
+        sql.table_name("user_totp"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(username_condition(username)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+pub async fn enabled(sql: &sql::Database, username: &str) -> CustomResult<bool> {
+    Ok(find(sql, username)
+        .await?
+        .is_some_and(|totp| totp.confirmed_at != 0))
+}
+
+// 条件更新，读取后记录已被其他请求修改时不写入，返回是否写入成功
+async fn update_if(
+    sql: &sql::Database,
+    username: &str,
+    field: &str,
+    value: SafeValue,
+    guard: Condition,
+) -> CustomResult<bool> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Update,
+        sql.table_name("user_totp"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value(field.to_string(), value)?
+        .add_condition(WhereClause::And(vec![
+            username_condition(username)?,
+            WhereClause::Condition(guard),
+        ]));
+    Ok(sql.execute_affected(&builder).await? == 1)
+}
+
+// 接受当前验证码或一次性恢复码，同一时间步的验证码不能重复使用
+async fn verify_code(
+    sql: &sql::Database,
+    username: &str,
+    mut totp: UserTotp,
+    code: &str,
+) -> CustomResult<bool> {
+    let now = Utc::now().timestamp() as u64;
+    if let Some(step) = totp::verify(&totp.secret, code, now)? {
+        // 时间步只能前进，并发提交同一验证码时只有一个请求能写入
+        return update_if(
+            sql,
+            username,
+            "last_step",
+            SafeValue::Integer(step),
+            Condition::new("last_step".to_string(), Operator::Lt, Some(SafeValue::Integer(step)))?,
+        )
+        .await;
+    }
+
+    let hash = totp::hash_recovery_code(code);
+    let Some(index) = totp.recovery_codes.iter().position(|h| h == &hash) else {
+        return Ok(false);
+    };
+    let previous = totp.recovery_codes.join(" ");
+    totp.recovery_codes.remove(index);
+    // 以读取到的恢复码列表为条件，同一恢复码并发使用时只有一个请求能成功
+    update_if(
+        sql,
+        username,
+        "recovery_codes",
+        SafeValue::Text(totp.recovery_codes.join(" "), ValidationLevel::Standard),
+        Condition::new(
+            "recovery_codes".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Text(previous, ValidationLevel::Standard)),
+        )?,
+    )
+    .await
+}
+
+#[derive(Serialize, Debug)]
+pub struct MfaChallenge {
+    mfa_token: String,
+}
+
+pub fn challenge(username: &str) -> CustomResult<MfaChallenge> {
+    Ok(MfaChallenge {
+        mfa_token: jwt::generate_jwt(
+            jwt::CustomClaims {
+                name: username.to_string(),
+                role: MFA_PENDING_ROLE.to_string(),
+            },
+            Duration::minutes(5),
+        )?,
+    })
+}
+
+fn current_user(principal: &Principal) -> AppResult<&str> {
+    match principal {
+        Principal::User(claims) => Ok(&claims.name),
+        Principal::ApiKey(_) => Err(CustomError::localized(ErrorKind::Forbidden, "mfa.api_key_forbidden")),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CodeData {
+    code: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EnrollResponse {
+    secret: String,
+    uri: String,
+}
+
+// 生成新的密钥，需要调用 confirm 提交一次验证码后才会启用
+#[post("/totp/enroll")]
+pub async fn enroll_handler(
+    _stage: Installed,
+    principal: Principal,
+    state: &State<Arc<AppState>>,
+) -> AppResult<Json<EnrollResponse>> {
+    let username = current_user(&principal)?;
+    let sql = state.sql_get().await?;
+    if !users::user_exists(&sql, username).await? {
+        return Err(CustomError::localized(ErrorKind::NotFound, "user.not_found"));
+    }
+
+    let existing = find(&sql, username).await?;
+    if existing.as_ref().is_some_and(|totp| totp.confirmed_at != 0) {
+        return Err(CustomError::localized(ErrorKind::Conflict, "mfa.already_enabled"));
+    }
+
+    let totp = UserTotp {
+        secret: totp::generate_secret(),
+        confirmed_at: 0,
+        last_step: 0,
+        recovery_codes: Vec::new(),
+    };
+    save(&sql, username, &totp, existing.is_some())
+        .await?;
+
+    Ok(Json(EnrollResponse {
+        uri: totp::provisioning_uri(&totp.secret, username)?,
+        secret: totp.secret,
+    }))
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConfirmResponse {
+    recovery_codes: Vec<String>,
+}
+
+// 恢复码只在启用时返回一次
+#[post("/totp/confirm", format = "application/json", data = "<data>")]
+pub async fn confirm_handler(
+    _stage: Installed,
+    principal: Principal,
+    state: &State<Arc<AppState>>,
+    data: Json<CodeData>,
+) -> AppResult<Json<ConfirmResponse>> {
+    let username = current_user(&principal)?;
+    let sql = state.sql_get().await?;
+
+    let Some(mut totp) = find(&sql, username).await? else {
+        return Err(CustomError::localized(ErrorKind::NotFound, "mfa.not_enrolled"));
+    };
+    if totp.confirmed_at != 0 {
+        return Err(CustomError::localized(ErrorKind::Conflict, "mfa.already_enabled"));
+    }
+    let now = Utc::now().timestamp();
+    let Some(step) = totp::verify(&totp.secret, &data.code, now as u64)? else {
+        return Err(CustomError::localized(ErrorKind::Validation, "mfa.invalid_code"));
+    };
+
+    let recovery_codes = totp::generate_recovery_codes();
+    totp.confirmed_at = now;
+    totp.last_step = step;
+    totp.recovery_codes = recovery_codes
+        .iter()
+        .map(|code| totp::hash_recovery_code(code))
+        .collect();
+    save(&sql, username, &totp, true).await?;
+
+    Ok(Json(ConfirmResponse { recovery_codes }))
+}
+
+#[post("/totp/disable", format = "application/json", data = "<data>")]
+pub async fn disable_handler(
+    _stage: Installed,
+    principal: Principal,
+    state: &State<Arc<AppState>>,
+    locale: Locale,
+    data: Json<CodeData>,
+) -> AppResult<String> {
+    let username = current_user(&principal)?;
+    let sql = state.sql_get().await?;
+
+    let Some(totp) = find(&sql, username).await? else {
+        return Err(CustomError::localized(ErrorKind::NotFound, "mfa.not_enabled"));
+    };
+    if totp.confirmed_at != 0
+        && !verify_code(&sql, username, totp, &data.code)
+            .await?
+    {
+        return Err(CustomError::localized(ErrorKind::Forbidden, "mfa.invalid_code"));
+    }
+    disable(&sql, username).await?;
+    Ok(locale.text("mfa.disabled"))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MfaLoginData {
+    mfa_token: String,
+    code: String,
+}
+
+// 登录第二步，验证码通过后签发正式令牌
+#[post("/mfa", format = "application/json", data = "<data>")]
+pub async fn token_mfa(
+    _stage: Installed,
+    throttle: LoginThrottle,
+    state: &State<Arc<AppState>>,
+    data: Json<MfaLoginData>,
+) -> AppResult<LoginReply> {
+    let claims = jwt::validate_jwt(&data.mfa_token)
+        .ok()
+        .filter(|claims| claims.role == MFA_PENDING_ROLE)
+        .ok_or_else(|| CustomError::localized(ErrorKind::Unauthorized, "mfa.invalid_token"))?;
+    throttle.check(&claims.name).await?;
+
+    let sql = state.sql_get().await?;
+    let totp = find(&sql, &claims.name)
+        .await?
+        .filter(|totp| totp.confirmed_at != 0)
+        .ok_or_else(|| CustomError::localized(ErrorKind::Validation, "mfa.not_enabled"))?;
+
+    if !verify_code(&sql, &claims.name, totp, &data.code)
+        .await?
+    {
+        throttle.failed(&claims.name).await?;
+        return Err(CustomError::localized(ErrorKind::Unauthorized, "mfa.invalid_code"));
+    }
+    throttle.succeeded(&claims.name).await?;
+
+    Ok(LoginReply::Token(issue_token(&claims.name)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing::{self, TestSite};
+    use crate::api::users::{RegisterData, Role};
+    use crate::storage::sql::memory_database;
+    use rocket::http::{ContentType, Status};
+    use serde_json::Value;
+
+    async fn enrolled(recovery_codes: &[&str]) -> sql::Database {
+        let sql = memory_database().await;
+        sql.migrate(false).await.unwrap();
+        users::insert_user(
+            &sql,
+            RegisterData {
+                username: "alice".to_string(),
+                email: "alice@example.com".to_string(),
+                password: "correct-horse-battery".to_string(),
+                role: Role::Administrator,
+            },
+        )
+        .await
+        .unwrap();
+        let totp = UserTotp {
+            secret: totp::generate_secret(),
+            confirmed_at: 1,
+            last_step: 0,
+            recovery_codes: recovery_codes
+                .iter()
+                .map(|code| totp::hash_recovery_code(code))
+                .collect(),
+        };
+        save(&sql, "alice", &totp, false).await.unwrap();
+        sql
+    }
+
+    #[tokio::test]
+    async fn recovery_code_is_accepted_once_under_concurrent_use() {
+        let sql = enrolled(&["first-code", "second-code"]).await;
+        // 两个请求在任何一个写入前读取到相同的记录
+        let first = find(&sql, "alice").await.unwrap().unwrap();
+        let second = find(&sql, "alice").await.unwrap().unwrap();
+
+        assert!(verify_code(&sql, "alice", first, "first-code").await.unwrap());
+        assert!(!verify_code(&sql, "alice", second, "first-code").await.unwrap());
+
+        let current = find(&sql, "alice").await.unwrap().unwrap();
+        assert_eq!(current.recovery_codes.len(), 1);
+        assert!(verify_code(&sql, "alice", current, "second-code").await.unwrap());
+        let current = find(&sql, "alice").await.unwrap().unwrap();
+        assert!(!verify_code(&sql, "alice", current, "second-code").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn time_step_only_moves_forward() {
+        let sql = enrolled(&[]).await;
+        let step = |value: i64| {
+            Condition::new("last_step".to_string(), Operator::Lt, Some(SafeValue::Integer(value)))
+                .unwrap()
+        };
+        let claim = |value: i64| {
+            update_if(&sql, "alice", "last_step", SafeValue::Integer(value), step(value))
+        };
+
+        assert!(claim(5).await.unwrap());
+        assert!(!claim(5).await.unwrap());
+        assert!(!claim(4).await.unwrap());
+        assert!(claim(6).await.unwrap());
+        assert_eq!(find(&sql, "alice").await.unwrap().unwrap().last_step, 6);
+    }
+
+    async fn login(site: &TestSite, remote: &str, path: &str, body: Value) -> (Status, Value) {
+        let response = site
+            .client
+            .post(path)
+            .remote(remote.parse().unwrap())
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+            .await;
+        let status = response.status();
+        (status, response.into_json().await.unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn password_login_does_not_reset_code_failures() {
+        let site = testing::site(|config| {
+            config.login_limit.base_delay = 0;
+            config.login_limit.max_failures = 2;
+        })
+        .await;
+        site.add_user("alice", "alice@example.com", Role::Administrator)
+            .await;
+        let totp = UserTotp {
+            secret: totp::generate_secret(),
+            confirmed_at: 1,
+            last_step: 0,
+            recovery_codes: Vec::new(),
+        };
+        save(&site.sql, "alice", &totp, false).await.unwrap();
+
+        // 每个请求使用不同的地址，只观察用户名计数
+        let password =
+            serde_json::json!({ "username": "alice", "password": "correct-horse-battery" });
+        for (index, remote) in ["10.0.0.1:1", "10.0.0.2:1"].iter().enumerate() {
+            let (status, body) = login(&site, remote, "/auth/token/system", password.clone()).await;
+            assert_eq!(status, Status::Accepted, "第 {} 次密码登录", index + 1);
+            let code = serde_json::json!({ "mfa_token": body["mfa_token"], "code": "000000" });
+            let (status, _) = login(
+                &site,
+                &format!("10.0.1.{}:1", index),
+                "/auth/token/mfa",
+                code,
+            )
+            .await;
+            assert_eq!(status, Status::Unauthorized);
+        }
+
+        let (status, _) = login(&site, "10.0.0.3:1", "/auth/token/system", password).await;
+        assert_eq!(status, Status::TooManyRequests);
+    }
+}
@@ -1 +1,5 @@
+pub mod email;
+pub mod mfa;
+pub mod oidc;
 pub mod token;
+pub mod throttle;
@@ -0,0 +1,797 @@
+use super::mfa;
+use super::token::{issue_token, LoginReply};
+use crate::api::{users, Installed, Principal, Role};
+use crate::common::config::{self, OidcProviderConfig};
+use crate::common::error::{AppResult, CustomError, CustomErrorInto, CustomResult, ErrorKind};
+use crate::common::i18n::Locale;
+use crate::common::helpers;
+use crate::storage::sql::{
+    self,
+    builder::{self, Condition, Operator, SafeValue, SqlOperation, ValidationLevel, WhereClause},
+};
+use crate::AppState;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use rocket::serde::json::Json;
+use rocket::{get, post, Responder, State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+// 授权请求需在该秒数内完成回调
+const STATE_TTL: i64 = 600;
+
+fn text_condition(field: &str, value: &str) -> CustomResult<WhereClause> {
+    Ok(WhereClause::Condition(Condition::new(
+        field.to_string(),
+        Operator::Eq,
+        Some(SafeValue::Text(value.to_string(), ValidationLevel::Standard)),
+    )?))
+}
+
+fn hash(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+fn provider_config(name: &str) -> AppResult<OidcProviderConfig> {
+    config::Config::current()
+        .oidc
+        .get(name)
+        .cloned()
+        .ok_or_else(|| CustomError::localized(ErrorKind::NotFound, "oidc.unknown_provider").with_arg("name", name))
+}
+
+fn bad_gateway(error: impl std::fmt::Display) -> CustomError {
+    format!("身份提供方请求失败: {}", error).into_error(ErrorKind::Upstream)
+}
+
+#[derive(Deserialize, Debug)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+fn client() -> CustomResult<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?)
+}
+
+// ID 令牌不校验签名，由 TLS 保证来自提供方，开发模式以外只接受 https
+fn require_https(name: &str, url: &str) -> CustomResult<()> {
+    let scheme = url::Url::parse(url)?.scheme().to_string();
+    if scheme != "https" && !config::Config::current().dev_mode {
+        return Err(format!("{} 必须使用 https: {}", name, url).into_custom_error());
+    }
+    Ok(())
+}
+
+async fn discover(provider: &OidcProviderConfig) -> CustomResult<Discovery> {
+    require_https("issuer", &provider.issuer)?;
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        provider.issuer.trim_end_matches('/')
+    );
+    let discovery: Discovery = client()?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if discovery.issuer.trim_end_matches('/') != provider.issuer.trim_end_matches('/') {
+        return Err(format!("issuer 不一致: {}", discovery.issuer).into_custom_error());
+    }
+    require_https("token_endpoint", &discovery.token_endpoint)?;
+    Ok(discovery)
+}
+
+struct PendingState {
+    provider: String,
+    verifier: String,
+    nonce: String,
+    link_username: String,
+}
+
+async fn save_state(sql: &sql::Database, state: &str, pending: &PendingState) -> CustomResult<()> {
+    let now = Utc::now().timestamp();
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Delete,
+        sql.table_name("oidc_states"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(WhereClause::Condition(Condition::new(
+        "expires_at".to_string(),
+        Operator::Lt,
+        Some(SafeValue::Integer(now)),
+    )?));
+    sql.execute_query(&builder).await?;
+
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Insert,
+        sql.table_name("oidc_states"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value(
+            "state_hash".to_string(),
+            SafeValue::Text(hash(state), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "provider".to_string(),
+            SafeValue::Text(pending.provider.clone(), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "verifier".to_string(),
+            SafeValue::Text(pending.verifier.clone(), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "nonce".to_string(),
+            SafeValue::Text(pending.nonce.clone(), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "link_username".to_string(),
+            SafeValue::Text(pending.link_username.clone(), ValidationLevel::Standard),
+        )?
+        .set_value(
+            "expires_at".to_string(),
+            SafeValue::Integer(now + STATE_TTL),
+        )?;
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+// state 只能使用一次，读取后删除，删除成功的请求才能继续
+async fn take_state(sql: &sql::Database, state: &str) -> CustomResult<Option<PendingState>> {
+    if state.is_empty() || !state.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Ok(None);
+    }
+    let condition = text_condition("state_hash", &hash(state))?;
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Select,
+        sql.table_name("oidc_states"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(condition.clone());
+    let rows = sql.primary().execute_query(&builder).await?;
+    let Some(row) = rows.first() else {
+        return Ok(None);
+    };
+
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Delete,
+        sql.table_name("oidc_states"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(condition);
+    if sql.execute_affected(&builder).await? != 1 {
+        return Ok(None);
+    }
+
+    let text = |key: &str| {
+        row.get(key)
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let expires_at = row.get("expires_at").and_then(|value| value.as_i64()).unwrap_or(0);
+    if expires_at <= Utc::now().timestamp() {
+        return Ok(None);
+    }
+    Ok(Some(PendingState {
+        provider: text("provider"),
+        verifier: text("verifier"),
+        nonce: text("nonce"),
+        link_username: text("link_username"),
+    }))
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuthorizeResponse {
+    url: String,
+    state: String,
+}
+
+// 生成 state、nonce 和 PKCE 校验码，返回提供方的授权地址
+async fn begin(
+    sql: &sql::Database,
+    name: &str,
+    link_username: &str,
+) -> AppResult<AuthorizeResponse> {
+    let provider = provider_config(name)?;
+    let discovery = discover(&provider).await.map_err(bad_gateway)?;
+
+    let state = helpers::generate_random_string(32);
+    let pending = PendingState {
+        provider: name.to_string(),
+        verifier: helpers::generate_random_string(64),
+        nonce: helpers::generate_random_string(32),
+        link_username: link_username.to_string(),
+    };
+    save_state(sql, &state, &pending).await?;
+
+    let mut url = url::Url::parse(&discovery.authorization_endpoint).map_err(bad_gateway)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", &provider.redirect_url)
+        .append_pair("scope", &provider.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &pending.nonce)
+        .append_pair(
+            "code_challenge",
+            &URL_SAFE_NO_PAD.encode(Sha256::digest(pending.verifier.as_bytes())),
+        )
+        .append_pair("code_challenge_method", "S256");
+
+    Ok(AuthorizeResponse {
+        url: url.to_string(),
+        state,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdClaims {
+    iss: String,
+    sub: String,
+    aud: Value,
+    exp: i64,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+// ID 令牌直接从令牌端点获取，按 OIDC Core 3.1.3.7 以 TLS 校验代替签名校验（见 require_https），其余声明逐项检查
+async fn exchange(
+    provider: &OidcProviderConfig,
+    discovery: &Discovery,
+    code: &str,
+    pending: &PendingState,
+) -> CustomResult<IdClaims> {
+    let mut request = client()?.post(&discovery.token_endpoint).form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &provider.redirect_url),
+        ("client_id", &provider.client_id),
+        ("code_verifier", &pending.verifier),
+    ]);
+    if !provider.client_secret.is_empty() {
+        request = request.basic_auth(&provider.client_id, Some(&provider.client_secret));
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "令牌端点返回 {}: {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        )
+        .into_error(ErrorKind::Upstream));
+    }
+    let token: TokenResponse = response.json().await?;
+
+    let payload = token
+        .id_token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| CustomError::localized(ErrorKind::Unauthorized, "oidc.malformed_id_token"))?;
+    let claims: IdClaims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)?;
+
+    let audience_matches = match &claims.aud {
+        Value::String(aud) => aud == &provider.client_id,
+        Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(&provider.client_id)),
+        _ => false,
+    };
+    if claims.iss.trim_end_matches('/') != discovery.issuer.trim_end_matches('/')
+        || !audience_matches
+        || claims.exp <= Utc::now().timestamp()
+        || claims.nonce.as_deref() != Some(pending.nonce.as_str())
+        || claims.sub.is_empty()
+    {
+        return Err(CustomError::localized(ErrorKind::Unauthorized, "oidc.invalid_id_token"));
+    }
+    Ok(claims)
+}
+
+// subject 由提供方决定格式，可能包含 | 等字符
+fn identity_condition(provider: &str, subject: &str) -> CustomResult<WhereClause> {
+    Ok(WhereClause::And(vec![
+        text_condition("provider", provider)?,
+        WhereClause::Condition(Condition::new(
+            "subject".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Text(subject.to_string(), ValidationLevel::Relaxed)),
+        )?),
+    ]))
+}
+
+async fn find_identity(sql: &sql::Database, provider: &str, subject: &str) -> CustomResult<Option<String>> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Select,
+        sql.table_name("user_identities"),
+        sql.get_type(),
+    )?;
+    builder
+        .add_field("username".to_string())?
+        .add_condition(identity_condition(provider, subject)?);
+    Ok(sql
+        .primary()
+        .execute_query(&builder)
+        .await?
+        .first()
+        .and_then(|row| row.get("username"))
+        .and_then(|value| value.as_str())
+        .map(String::from))
+}
+
+async fn link_identity(
+    sql: &sql::Database,
+    provider: &str,
+    subject: &str,
+    username: &str,
+) -> CustomResult<()> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Insert,
+        sql.table_name("user_identities"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value(
+            "provider".to_string(),
+            SafeValue::Text(provider.to_string(), ValidationLevel::Strict),
+        )?
+        .set_value(
+            "subject".to_string(),
+            SafeValue::Text(subject.to_string(), ValidationLevel::Relaxed),
+        )?
+        .set_value(
+            "username".to_string(),
+            SafeValue::Text(username.to_string(), ValidationLevel::Standard),
+        )?
+        .set_value(
+            "created_at".to_string(),
+            SafeValue::Integer(Utc::now().timestamp()),
+        )?;
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+// 已关联的身份直接返回用户，否则在允许时按已验证的邮箱自动关联
+async fn resolve_user(
+    sql: &sql::Database,
+    name: &str,
+    provider: &OidcProviderConfig,
+    claims: &IdClaims,
+) -> CustomResult<Option<String>> {
+    if let Some(username) = find_identity(sql, name, &claims.sub).await? {
+        return Ok(Some(username));
+    }
+    let Some(email) = claims.email.as_deref().filter(|_| provider.link_by_email && claims.email_verified)
+    else {
+        return Ok(None);
+    };
+    let Some((username, _, _)) = users::contact_by_email(sql, email).await? else {
+        return Ok(None);
+    };
+    link_identity(sql, name, &claims.sub, &username).await?;
+    Ok(Some(username))
+}
+
+#[get("/providers")]
+pub async fn providers_handler(_stage: Installed) -> Json<Vec<String>> {
+    Json(config::Config::current().oidc.keys().cloned().collect())
+}
+
+#[post("/<name>/authorize")]
+pub async fn authorize_handler(
+    _stage: Installed,
+    name: &str,
+    state: &State<Arc<AppState>>,
+) -> AppResult<Json<AuthorizeResponse>> {
+    let sql = state.sql_get().await?;
+    Ok(Json(begin(&sql, name, "").await?))
+}
+
+fn current_user(principal: &Principal) -> AppResult<&str> {
+    match principal {
+        Principal::User(claims) => Ok(&claims.name),
+        Principal::ApiKey(_) => Err(CustomError::localized(ErrorKind::Forbidden, "oidc.api_key_forbidden")),
+    }
+}
+
+// 已登录用户发起关联，回调时把外部身份关联到该用户
+#[post("/<name>/link")]
+pub async fn link_handler(
+    _stage: Installed,
+    name: &str,
+    principal: Principal,
+    state: &State<Arc<AppState>>,
+) -> AppResult<Json<AuthorizeResponse>> {
+    let username = current_user(&principal)?;
+    let sql = state.sql_get().await?;
+    if !users::user_exists(&sql, username).await? {
+        return Err(CustomError::localized(ErrorKind::NotFound, "user.not_found"));
+    }
+    Ok(Json(begin(&sql, name, username).await?))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CallbackData {
+    code: String,
+    state: String,
+}
+
+#[derive(Responder)]
+pub enum CallbackReply {
+    Login(LoginReply),
+    Linked(String),
+}
+
+// 前端收到提供方的回调后提交 code 和 state，登录时返回与密码登录相同的结果
+#[post("/<name>/callback", format = "application/json", data = "<data>")]
+pub async fn callback_handler(
+    _stage: Installed,
+    name: &str,
+    state: &State<Arc<AppState>>,
+    locale: Locale,
+    data: Json<CallbackData>,
+) -> AppResult<CallbackReply> {
+    let sql = state.sql_get().await?;
+    let pending = take_state(&sql, &data.state)
+        .await?
+        .filter(|pending| pending.provider == name)
+        .ok_or_else(|| CustomError::localized(ErrorKind::Validation, "oidc.invalid_state"))?;
+
+    let provider = provider_config(name)?;
+    let discovery = discover(&provider).await.map_err(bad_gateway)?;
+    let claims = exchange(&provider, &discovery, &data.code, &pending).await?;
+
+    if !pending.link_username.is_empty() {
+        return match find_identity(&sql, name, &claims.sub).await? {
+            Some(username) if username != pending.link_username => Err(CustomError::localized(ErrorKind::Conflict, "oidc.linked_elsewhere")),
+            Some(_) => Ok(CallbackReply::Linked(locale.text("oidc.linked"))),
+            None => {
+                link_identity(&sql, name, &claims.sub, &pending.link_username)
+                    .await?;
+                Ok(CallbackReply::Linked(locale.text("oidc.linked")))
+            }
+        };
+    }
+
+    let Some(username) = resolve_user(&sql, name, &provider, &claims)
+        .await?
+    else {
+        return Err(CustomError::localized(ErrorKind::Forbidden, "oidc.not_linked"));
+    };
+    // 与密码登录一致，只有管理员可以登录
+    if users::role_of(&sql, &username).await?
+        != Some(Role::Administrator.to_string())
+    {
+        return Err(CustomError::localized(ErrorKind::Forbidden, "oidc.admin_only"));
+    }
+
+    if mfa::enabled(&sql, &username).await? {
+        return Ok(CallbackReply::Login(LoginReply::MfaRequired(Json(
+            mfa::challenge(&username)?,
+        ))));
+    }
+    Ok(CallbackReply::Login(LoginReply::Token(issue_token(&username)?)))
+}
+
+#[post("/<name>/unlink")]
+pub async fn unlink_handler(
+    _stage: Installed,
+    name: &str,
+    principal: Principal,
+    state: &State<Arc<AppState>>,
+    locale: Locale,
+) -> AppResult<String> {
+    let username = current_user(&principal)?;
+    let sql = state.sql_get().await?;
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Delete,
+        sql.table_name("user_identities"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(WhereClause::And(vec![
+        text_condition("provider", name)?,
+        text_condition("username", username)?,
+    ]));
+    sql.execute_query(&builder).await?;
+    Ok(locale.text("oidc.unlinked"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing::{self, TestSite};
+    use rocket::http::{ContentType, Status};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // 本地身份提供方，授权码对应测试登记的 PKCE 挑战和 ID 令牌声明
+    #[derive(Clone)]
+    struct MockProvider {
+        issuer: String,
+        codes: Arc<Mutex<HashMap<String, (String, Value)>>>,
+    }
+
+    impl MockProvider {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let provider = MockProvider {
+                issuer: format!("http://{}", listener.local_addr().unwrap()),
+                codes: Arc::default(),
+            };
+            let server = provider.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let server = server.clone();
+                    tokio::spawn(async move { server.serve(stream).await });
+                }
+            });
+            provider
+        }
+
+        fn grant(&self, code: &str, challenge: &str, claims: Value) {
+            self.codes
+                .lock()
+                .unwrap()
+                .insert(code.to_string(), (challenge.to_string(), claims));
+        }
+
+        async fn serve(&self, mut stream: TcpStream) {
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            let (head, body) = loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    return;
+                }
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                let Some(end) = text.find("\r\n\r\n") else {
+                    continue;
+                };
+                let length = text[..end]
+                    .lines()
+                    .find_map(|line| {
+                        line.to_ascii_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|value| value.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if request.len() >= end + 4 + length {
+                    break (text[..end].to_string(), text[end + 4..].to_string());
+                }
+            };
+
+            let path = head.split(' ').nth(1).unwrap_or_default();
+            let (status, reply) = match path {
+                "/.well-known/openid-configuration" => (
+                    "200 OK",
+                    serde_json::json!({
+                        "issuer": self.issuer,
+                        "authorization_endpoint": format!("{}/authorize", self.issuer),
+                        "token_endpoint": format!("{}/token", self.issuer),
+                    }),
+                ),
+                "/token" => self.token(&body),
+                _ => ("404 Not Found", serde_json::json!({})),
+            };
+            let reply = reply.to_string();
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                reply.len(),
+                reply
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+
+        fn token(&self, body: &str) -> (&'static str, Value) {
+            let form: HashMap<String, String> = url::form_urlencoded::parse(body.as_bytes())
+                .into_owned()
+                .collect();
+            let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+            let granted = form
+                .get("code")
+                .and_then(|code| self.codes.lock().unwrap().remove(code));
+            match granted {
+                Some((challenge, claims))
+                    if URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) == challenge =>
+                {
+                    let id_token = format!(
+                        "{}.{}.signature",
+                        URL_SAFE_NO_PAD.encode(br#"{"alg":"none"}"#),
+                        URL_SAFE_NO_PAD.encode(claims.to_string())
+                    );
+                    ("200 OK", serde_json::json!({ "id_token": id_token }))
+                }
+                _ => (
+                    "400 Bad Request",
+                    serde_json::json!({ "error": "invalid_grant" }),
+                ),
+            }
+        }
+    }
+
+    async fn site(provider: &MockProvider, dev_mode: bool) -> TestSite {
+        let issuer = provider.issuer.clone();
+        let site = testing::site(|config| {
+            config.dev_mode = dev_mode;
+            config.oidc.insert(
+                "mock".to_string(),
+                OidcProviderConfig {
+                    issuer,
+                    client_id: "echoes".to_string(),
+                    redirect_url: "https://example.com/callback".to_string(),
+                    link_by_email: true,
+                    ..Default::default()
+                },
+            );
+        })
+        .await;
+        site.add_user("alice", "alice@example.com", Role::Administrator)
+            .await;
+        site
+    }
+
+    struct Authorization {
+        state: String,
+        nonce: String,
+        challenge: String,
+    }
+
+    async fn authorize(site: &TestSite) -> Authorization {
+        let response = site
+            .client
+            .post("/auth/oidc/mock/authorize")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = response.into_json().await.unwrap();
+        let url = url::Url::parse(body["url"].as_str().unwrap()).unwrap();
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["state"], body["state"].as_str().unwrap());
+        Authorization {
+            state: query["state"].clone(),
+            nonce: query["nonce"].clone(),
+            challenge: query["code_challenge"].clone(),
+        }
+    }
+
+    fn claims(provider: &MockProvider, nonce: &str) -> Value {
+        serde_json::json!({
+            "iss": provider.issuer,
+            "sub": "mock-alice",
+            "aud": "echoes",
+            "exp": Utc::now().timestamp() + 300,
+            "nonce": nonce,
+            "email": "alice@example.com",
+            "email_verified": true,
+        })
+    }
+
+    async fn callback(site: &TestSite, code: &str, state: &str) -> (Status, String) {
+        let response = site
+            .client
+            .post("/auth/oidc/mock/callback")
+            .header(ContentType::JSON)
+            .body(serde_json::json!({ "code": code, "state": state }).to_string())
+            .dispatch()
+            .await;
+        let status = response.status();
+        (status, response.into_string().await.unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn callback_issues_a_token() {
+        let provider = MockProvider::start().await;
+        let site = site(&provider, true).await;
+        let authorization = authorize(&site).await;
+        provider.grant(
+            "code-1",
+            &authorization.challenge,
+            claims(&provider, &authorization.nonce),
+        );
+
+        let (status, token) = callback(&site, "code-1", &authorization.state).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(
+            crate::security::jwt::validate_jwt(&token).unwrap().name,
+            "alice"
+        );
+        // 按邮箱自动关联后，身份与用户绑定
+        assert_eq!(
+            find_identity(&site.sql, "mock", "mock-alice")
+                .await
+                .unwrap(),
+            Some("alice".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn mismatched_nonce_and_audience_are_rejected() {
+        let provider = MockProvider::start().await;
+        let site = site(&provider, true).await;
+
+        let authorization = authorize(&site).await;
+        provider.grant(
+            "code-nonce",
+            &authorization.challenge,
+            claims(&provider, "another-nonce"),
+        );
+        let (status, _) = callback(&site, "code-nonce", &authorization.state).await;
+        assert_eq!(status, Status::Unauthorized);
+
+        let authorization = authorize(&site).await;
+        let mut claims = claims(&provider, &authorization.nonce);
+        claims["aud"] = serde_json::json!(["another-client"]);
+        provider.grant("code-aud", &authorization.challenge, claims);
+        let (status, _) = callback(&site, "code-aud", &authorization.state).await;
+        assert_eq!(status, Status::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn expired_and_replayed_states_are_rejected() {
+        let provider = MockProvider::start().await;
+        let site = site(&provider, true).await;
+
+        let authorization = authorize(&site).await;
+        let mut builder = builder::QueryBuilder::new(
+            SqlOperation::Update,
+            site.sql.table_name("oidc_states"),
+            site.sql.get_type(),
+        )
+        .unwrap();
+        builder
+            .set_value("expires_at".to_string(), SafeValue::Integer(0))
+            .unwrap()
+            .add_condition(text_condition("state_hash", &hash(&authorization.state)).unwrap());
+        site.sql.execute_query(&builder).await.unwrap();
+        provider.grant(
+            "code-expired",
+            &authorization.challenge,
+            claims(&provider, &authorization.nonce),
+        );
+        let (status, _) = callback(&site, "code-expired", &authorization.state).await;
+        assert_eq!(status, Status::BadRequest);
+
+        let authorization = authorize(&site).await;
+        for code in ["code-first", "code-replay"] {
+            provider.grant(
+                code,
+                &authorization.challenge,
+                claims(&provider, &authorization.nonce),
+            );
+        }
+        let (status, _) = callback(&site, "code-first", &authorization.state).await;
+        assert_eq!(status, Status::Ok);
+        let (status, _) = callback(&site, "code-replay", &authorization.state).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn http_issuer_requires_dev_mode() {
+        let provider = MockProvider::start().await;
+        let site = site(&provider, false).await;
+        let response = site
+            .client
+            .post("/auth/oidc/mock/authorize")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadGateway);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::common::config::{self, LoginLimitConfig};
+use crate::common::error::{AppResult, CustomError, CustomResult, ErrorKind};
+use crate::storage::sql::{
+    self,
+    builder::{self, Condition, Operator, SafeValue, SqlOperation, ValidationLevel, WhereClause},
+    DatabaseType,
+};
+use crate::AppState;
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+struct Attempt {
+    failures: i64,
+    last_failure_at: i64,
+    locked_until: i64,
+}
+
+impl Attempt {
+    // 距离允许再次尝试还需等待的秒数
+    fn wait(&self, limit: &LoginLimitConfig, now: i64) -> i64 {
+        if self.locked_until > now {
+            return self.locked_until - now;
+        }
+        if self.failures <= 0 {
+            return 0;
+        }
+        let delay = limit
+            .base_delay
+            .saturating_mul(1u64 << (self.failures - 1).min(32))
+            .min(limit.max_delay) as i64;
+        (self.last_failure_at + delay - now).max(0)
+    }
+
+    // 超过计数窗口且未锁定的记录视为已失效
+    fn expired(&self, limit: &LoginLimitConfig, now: i64) -> bool {
+        self.locked_until <= now && now - self.last_failure_at > limit.window as i64
+    }
+}
+
+fn ip_subject(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+// 用户名、邮箱可能包含任意字符，取哈希后存储
+fn hashed_subject(kind: &str, value: &str) -> String {
+    format!(
+        "{}:{}",
+        kind,
+        hex::encode(Sha256::digest(value.trim().to_lowercase().as_bytes()))
+    )
+}
+
+fn user_subject(username: &str) -> String {
+    hashed_subject("user", username)
+}
+
+fn email_subject(email: &str) -> String {
+    hashed_subject("email", email)
+}
+
+fn subject_condition(subject: &str) -> CustomResult<WhereClause> {
+    Ok(WhereClause::Condition(Condition::new(
+        "subject".to_string(),
+        Operator::Eq,
+        Some(SafeValue::Text(subject.to_string(), ValidationLevel::Standard)),
+    )?))
+}
+
+async fn find(sql: &sql::Database, subject: &str) -> CustomResult<Option<Attempt>> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Select,
+        sql.table_name("login_attempts"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(subject_condition(subject)?);
+    Ok(sql
+        .primary()
+        .execute_query(&builder)
+        .await?
+        .first()
+        .map(|row| {
+            let number = |key: &str| row.get(key).and_then(|value| value.as_i64()).unwrap_or(0);
+            Attempt {
+                failures: number("failures"),
+                last_failure_at: number("last_failure_at"),
+                locked_until: number("locked_until"),
+            }
+        }))
+}
+
+async fn wait_for(sql: &sql::Database, subject: &str) -> CustomResult<i64> {
+    let limit = config::Config::current().login_limit.clone();
+    let now = Utc::now().timestamp();
+    Ok(find(sql, subject)
+        .await?
+        .filter(|attempt| !attempt.expired(&limit, now))
+        .map(|attempt| attempt.wait(&limit, now))
+        .unwrap_or(0))
+}
+
+// 计数在一条 upsert 中完成，并发的失败请求不会互相覆盖；超过计数窗口且未锁定时重新从 1 开始。
+// MySQL 按书写顺序赋值，failures 需要写在 last_failure_at 之前，才能读到旧的失败时间
+async fn record_failure(sql: &sql::Database, subject: &str) -> CustomResult<()> {
+    let limit = config::Config::current().login_limit.clone();
+    let now = Utc::now().timestamp();
+    let table = sql.table_name("login_attempts");
+    let db_type = sql.get_type();
+    let param = |index: usize| match db_type {
+        DatabaseType::PostgreSQL => format!("${}", index),
+        DatabaseType::MySQL | DatabaseType::SQLite => "?".to_string(),
+    };
+
+    let (conflict, column) = match db_type {
+        DatabaseType::MySQL => ("ON DUPLICATE KEY UPDATE".to_string(), String::new()),
+        DatabaseType::PostgreSQL | DatabaseType::SQLite => {
+            ("ON CONFLICT (subject) DO UPDATE SET".to_string(), format!("{}.", table))
+        }
+    };
+    let query = format!(
+        "INSERT INTO {table} (subject, failures, last_failure_at, locked_until) VALUES ({}, 1, {}, 0) \
+         {conflict} failures = CASE WHEN {column}locked_until <= {} AND {} - {column}last_failure_at > {} \
+         THEN 1 ELSE {column}failures + 1 END, last_failure_at = {}",
+        param(1),
+        param(2),
+        param(3),
+        param(4),
+        param(5),
+        param(6),
+    );
+    sql.execute_raw(
+        &query,
+        vec![
+            SafeValue::Text(subject.to_string(), ValidationLevel::Standard),
+            SafeValue::Integer(now),
+            SafeValue::Integer(now),
+            SafeValue::Integer(now),
+            SafeValue::Integer(limit.window as i64),
+            SafeValue::Integer(now),
+        ],
+    )
+    .await?;
+
+    // 按写入后的次数判断是否锁定
+    let mut builder = builder::QueryBuilder::new(SqlOperation::Update, table, db_type)?;
+    builder
+        .set_value(
+            "locked_until".to_string(),
+            SafeValue::Integer(now + limit.lockout as i64),
+        )?
+        .add_condition(WhereClause::And(vec![
+            subject_condition(subject)?,
+            WhereClause::Condition(Condition::new(
+                "failures".to_string(),
+                Operator::Gte,
+                Some(SafeValue::Integer(limit.max_failures as i64)),
+            )?),
+        ]));
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+async fn clear(sql: &sql::Database, subject: &str) -> CustomResult<()> {
+    let mut builder = builder::QueryBuilder::new(
+        SqlOperation::Delete,
+        sql.table_name("login_attempts"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(subject_condition(subject)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+// 解除用户名锁定，供命令行找回账户使用
+pub async fn clear_user(sql: &sql::Database, username: &str) -> CustomResult<()> {
+    clear(sql, &user_subject(username)).await
+}
+
+fn too_many_requests(wait: i64) -> CustomError {
+    CustomError::localized(ErrorKind::TooManyRequests, "auth.too_many_attempts").with_arg("seconds", wait)
+}
+
+// 登录限流守卫：IP 被限制时直接返回 429，用户名需要在读取请求体后调用 check，
+// 校验密码后调用 failed 或 succeeded 记录结果，发送邮件的请求调用 mailed
+pub struct LoginThrottle {
+    sql: sql::Database,
+    ip: Option<String>,
+}
+
+impl LoginThrottle {
+    pub async fn check(&self, username: &str) -> AppResult<()> {
+        let wait = wait_for(&self.sql, &user_subject(username))
+            .await?;
+        if wait > 0 {
+            return Err(too_many_requests(wait));
+        }
+        Ok(())
+    }
+
+    pub async fn failed(&self, username: &str) -> AppResult<()> {
+        if let Some(ip) = &self.ip {
+            record_failure(&self.sql, &ip_subject(ip))
+                .await?;
+        }
+        record_failure(&self.sql, &user_subject(username))
+            .await
+    }
+
+    // 会发送邮件的请求不论结果都计入邮箱和 IP 的次数，限制向同一地址反复发信
+    pub async fn mailed(&self, email: &str) -> AppResult<()> {
+        let subject = email_subject(email);
+        let wait = wait_for(&self.sql, &subject).await?;
+        if wait > 0 {
+            return Err(too_many_requests(wait));
+        }
+        if let Some(ip) = &self.ip {
+            record_failure(&self.sql, &ip_subject(ip)).await?;
+        }
+        record_failure(&self.sql, &subject).await
+    }
+
+    // 只清除用户名计数，避免用一个有效账户重置同一 IP 对其他账户的尝试次数
+    pub async fn succeeded(&self, username: &str) -> AppResult<()> {
+        clear(&self.sql, &user_subject(username))
+            .await
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LoginThrottle {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(state) = request.rocket().state::<Arc<AppState>>() else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+        let Ok(sql) = state.sql_get().await else {
+            return Outcome::Error((Status::ServiceUnavailable, ()));
+        };
+        let ip = request.client_ip().map(|ip| ip.to_string());
+
+        if let Some(ip) = &ip {
+            match wait_for(&sql, &ip_subject(ip)).await {
+                Ok(0) => {}
+                Ok(_) => return Outcome::Error((Status::TooManyRequests, ())),
+                Err(_) => return Outcome::Error((Status::InternalServerError, ())),
+            }
+        }
+        Outcome::Success(LoginThrottle { sql, ip })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing;
+
+    async fn attempt(sql: &sql::Database, subject: &str) -> Attempt {
+        find(sql, subject).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn concurrent_failures_are_all_counted() {
+        let site = testing::site(|config| config.login_limit.max_failures = 3).await;
+        let subject = user_subject("alice");
+
+        // 两个失败请求交替执行，都在对方写入前开始
+        let (first, second) = tokio::join!(
+            record_failure(&site.sql, &subject),
+            record_failure(&site.sql, &subject)
+        );
+        first.unwrap();
+        second.unwrap();
+        let current = attempt(&site.sql, &subject).await;
+        assert_eq!(current.failures, 2);
+        assert_eq!(current.locked_until, 0);
+
+        record_failure(&site.sql, &subject).await.unwrap();
+        let current = attempt(&site.sql, &subject).await;
+        assert_eq!(current.failures, 3);
+        assert!(current.locked_until > Utc::now().timestamp());
+        assert!(wait_for(&site.sql, &subject).await.unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn failures_restart_after_the_window() {
+        let site = testing::site(|_| {}).await;
+        let subject = ip_subject("127.0.0.1");
+        record_failure(&site.sql, &subject).await.unwrap();
+        record_failure(&site.sql, &subject).await.unwrap();
+
+        let mut builder = builder::QueryBuilder::new(
+            SqlOperation::Update,
+            site.sql.table_name("login_attempts"),
+            site.sql.get_type(),
+        )
+        .unwrap();
+        builder
+            .set_value("last_failure_at".to_string(), SafeValue::Integer(0))
+            .unwrap()
+            .add_condition(subject_condition(&subject).unwrap());
+        site.sql.execute_query(&builder).await.unwrap();
+
+        record_failure(&site.sql, &subject).await.unwrap();
+        assert_eq!(attempt(&site.sql, &subject).await.failures, 1);
+    }
+}
@@ -1,12 +1,15 @@
-use crate::common::error::{AppResult, AppResultInto};
+use crate::common::error::{AppResult, CustomError, ErrorKind};
 use crate::security;
 use crate::storage::sql::builder;
 use crate::AppState;
 use chrono::Duration;
-use rocket::{http::Status, post,get, response::status, serde::json::Json, State};
+use rocket::{post,get, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use crate::api::Role;
+use crate::api::{users, Installed, Role};
+use super::mfa::{self, MfaChallenge};
+use super::throttle::LoginThrottle;
+use rocket::Responder;
 
 #[derive(Deserialize, Serialize)]
 pub struct TokenData {
@@ -15,19 +18,22 @@ pub struct TokenData {
 }
 #[post("/system", format = "application/json", data = "<data>")]
 pub async fn token_system(
+    _stage: Installed,
+    throttle: LoginThrottle,
     state: &State<Arc<AppState>>,
     data: Json<TokenData>,
-) -> AppResult<String> {
-    let sql = state.sql_get().await.into_app_result()?;
+) -> AppResult<LoginReply> {
+    // 在校验密码前拒绝被限制的请求，避免反复计算哈希
+    throttle.check(&data.username).await?;
+
+    let sql = state.sql_get().await?;
     let mut builder = builder::QueryBuilder::new(
         builder::SqlOperation::Select,
         sql.table_name("users"),
         sql.get_type(),
-    )
-    .into_app_result()?;
+    )?;
     builder
-        .add_field("password_hash".to_string())
-        .into_app_result()?
+        .add_field("password_hash".to_string())?
         .add_condition(builder::WhereClause::And(vec![
             builder::WhereClause::Condition(
                 builder::Condition::new(
@@ -37,8 +43,7 @@ pub async fn token_system(
                         data.username.clone(),
                         builder::ValidationLevel::Relaxed,
                     )),
-                )
-                .into_app_result()?,
+                )?,
             ),
             builder::WhereClause::Condition(
                 builder::Condition::new(
@@ -48,46 +53,104 @@ pub async fn token_system(
                         "administrator".into(),
                         builder::ValidationLevel::Standard,
                     )),
-                )
-                .into_app_result()?,
+                )?,
             ),
         ]));
 
     let values = sql
-        .get_db()
         .execute_query(&builder)
-        .await
-        .into_app_result()?;
+        .await?;
 
 
-    let password = values
+    // 用户不存在和密码错误返回相同的结果，且同样计算一次哈希
+    let stored = values
         .first()
         .and_then(|row| row.get("password_hash"))
-        .and_then(|val| val.as_str())
-        .ok_or_else(|| status::Custom(Status::NotFound, "系统用户或密码无效".into()))?;
+        .and_then(|val| val.as_str());
+    let password = match stored {
+        Some(hash) => hash.to_string(),
+        None => security::password::dummy_hash()?,
+    };
+    if security::password::verify_hash(&data.password, &password).is_err() || stored.is_none() {
+        throttle.failed(&data.username).await?;
+        return Err(CustomError::localized(ErrorKind::Unauthorized, "auth.invalid_credentials"));
+    }
 
-    security::bcrypt::verify_hash(&data.password, password)
-        .map_err(|_| status::Custom(Status::Forbidden, "密码无效".into()))?;
+    // 哈希参数调整后在登录时升级，失败不影响本次登录
+    if security::password::needs_rehash(&password) {
+        if let Err(e) = users::rehash_password(&sql, &data.username, &data.password).await {
+            eprintln!("用户 {} 的密码哈希升级失败: {}", data.username, e);
+        }
+    }
 
-    Ok(security::jwt::generate_jwt(
+    // 启用两步验证的用户先拿到临时令牌，提交验证码后再签发正式令牌。
+    // 失败计数在验证码通过后才清除，否则可以用密码登录反复重置验证码的尝试次数
+    if mfa::enabled(&sql, &data.username).await? {
+        return Ok(LoginReply::MfaRequired(Json(
+            mfa::challenge(&data.username)?,
+        )));
+    }
+    throttle.succeeded(&data.username).await?;
+    Ok(LoginReply::Token(issue_token(&data.username)?))
+}
+
+#[derive(Responder)]
+pub enum LoginReply {
+    Token(String),
+    #[response(status = 202)]
+    MfaRequired(Json<MfaChallenge>),
+}
+
+pub fn issue_token(username: &str) -> AppResult<String> {
+    security::jwt::generate_jwt(
         security::jwt::CustomClaims {
-            name: "system".into(),
+            name: username.to_string(),
             role: Role::Administrator.to_string(),
         },
         Duration::minutes(1),
     )
-    .into_app_result()?)
 }
 
-
+// 调试用，签发长期有效的管理员令牌，只在开发模式下挂载
 #[get("/test")]
-pub async fn test_token(state: &State<Arc<AppState>>) -> AppResult<String> {
+pub async fn test_token(_stage: Installed, state: &State<Arc<AppState>>) -> AppResult<String> {
     Ok(security::jwt::generate_jwt(
         security::jwt::CustomClaims {
             name: "system".into(),
             role: Role::Administrator.to_string(),
         },
         Duration::days(999),
-    )
-    .into_app_result()?)
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::testing;
+    use crate::api::users::Role;
+    use rocket::http::{ContentType, Status};
+
+    #[tokio::test]
+    async fn unknown_user_and_wrong_password_look_the_same() {
+        let site = testing::site(|_| {}).await;
+        site.add_user("alice", "alice@example.com", Role::Administrator)
+            .await;
+
+        let mut replies = Vec::new();
+        for (remote, username) in [("10.0.0.1:1", "alice"), ("10.0.0.2:1", "nobody")] {
+            let response = site
+                .client
+                .post("/auth/token/system")
+                .remote(remote.parse().unwrap())
+                .header(ContentType::JSON)
+                .body(
+                    serde_json::json!({ "username": username, "password": "wrong-password" })
+                        .to_string(),
+                )
+                .dispatch()
+                .await;
+            replies.push((response.status(), response.into_string().await));
+        }
+        assert_eq!(replies[0].0, Status::Unauthorized);
+        assert_eq!(replies[0], replies[1]);
+    }
 }
@@ -1,12 +1,15 @@
-use super::SystemToken;
-use crate::common::error::{AppResult, AppResultInto, CustomErrorInto, CustomResult};
+use super::api_keys::Scope;
+use super::{Installed, Principal};
+use crate::common::error::{AppResult, CustomError, CustomResult, ErrorKind};
 use crate::storage::sql::{
     self,
     builder::{self, Condition, Operator, SafeValue, SqlOperation, ValidationLevel, WhereClause},
 };
 use crate::AppState;
+use rocket::response::status;
 use rocket::serde::json::Json;
 use rocket::{delete, get, post, put, State};
+use serde::Serialize;
 use serde_json::{from_str, json, to_value, Value};
 use std::fmt::{Display, Formatter};
 use std::sync::Arc;
@@ -37,7 +40,7 @@ impl TargetType {
             "page" => Ok(TargetType::Page),
             "theme" => Ok(TargetType::Theme),
             "system" => Ok(TargetType::System),
-            _ => Err("无效的目标类型".into_custom_error()),
+            _ => Err(CustomError::localized(ErrorKind::Validation, "fields.invalid_target")),
         }
     }
 }
@@ -62,7 +65,7 @@ impl FieldType {
         match s.to_lowercase().as_str() {
             "data" => Ok(FieldType::Data),
             "meta" => Ok(FieldType::Meta),
-            _ => Err("无效的字段类型".into_custom_error()),
+            _ => Err(CustomError::localized(ErrorKind::Validation, "fields.invalid_type")),
         }
     }
 }
@@ -74,7 +77,7 @@ pub async fn insert_fields(
     field_type: FieldType,
     field_key: &str,
     field_value: &str,
-) -> CustomResult<()> {
+) -> CustomResult<u64> {
     let mut builder = builder::QueryBuilder::new(
         SqlOperation::Insert,
         sql.table_name("fields"),
@@ -97,8 +100,7 @@ pub async fn insert_fields(
         "field_value".to_string(),
         SafeValue::Text(field_value.to_string(), ValidationLevel::Raw),
     )?;
-    sql.get_db().execute_query(&builder).await?;
-    Ok(())
+    sql.execute_affected(&builder).await
 }
 
 pub async fn get_field(
@@ -130,7 +132,7 @@ pub async fn get_field(
                 )),
             )?),
         ]));
-    let values = sql.get_db().execute_query(&builder).await?;
+    let values = sql.execute_query(&builder).await?;
 
     let processed_values = values
         .into_iter()
@@ -154,46 +156,72 @@ pub async fn delete_fields(
     target_id: i64,
     field_type: FieldType,
     field_key: &str,
-) -> CustomResult<()> {
+) -> CustomResult<u64> {
     let mut builder = builder::QueryBuilder::new(
         SqlOperation::Delete,
         sql.table_name("fields"),
         sql.get_type(),
     )?;
-    builder.set_value("target_id".to_string(), SafeValue::Integer(target_id))?;
-    builder.set_value(
-        "target_type".to_string(),
-        SafeValue::Text(target_type.to_string(), ValidationLevel::Standard),
-    )?;
-    builder.set_value(
-        "field_type".to_string(),
-        SafeValue::Text(field_type.to_string(), ValidationLevel::Standard),
-    )?;
-    builder.set_value(
-        "field_key".to_string(),
-        SafeValue::Text(field_key.to_string(), ValidationLevel::Standard),
-    )?;
-    sql.get_db().execute_query(&builder).await?;
-    Ok(())
+    builder.add_condition(WhereClause::And(vec![
+        WhereClause::Condition(Condition::new(
+            "target_id".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Integer(target_id)),
+        )?),
+        WhereClause::Condition(Condition::new(
+            "target_type".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Text(
+                target_type.to_string(),
+                ValidationLevel::Standard,
+            )),
+        )?),
+        WhereClause::Condition(Condition::new(
+            "field_type".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Text(
+                field_type.to_string(),
+                ValidationLevel::Standard,
+            )),
+        )?),
+        WhereClause::Condition(Condition::new(
+            "field_key".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Text(
+                field_key.to_string(),
+                ValidationLevel::Standard,
+            )),
+        )?),
+    ]));
+    sql.execute_affected(&builder).await
 }
 
 pub async fn delete_all_fields(
     sql: &sql::Database,
     target_type: TargetType,
     target_id: i64,
-) -> CustomResult<()> {
+) -> CustomResult<u64> {
     let mut builder = builder::QueryBuilder::new(
         SqlOperation::Delete,
         sql.table_name("fields"),
         sql.get_type(),
     )?;
-    builder.set_value("target_id".to_string(), SafeValue::Integer(target_id))?;
-    builder.set_value(
-        "target_type".to_string(),
-        SafeValue::Text(target_type.to_string(), ValidationLevel::Standard),
-    )?;
-    sql.get_db().execute_query(&builder).await?;
-    Ok(())
+    builder.add_condition(WhereClause::And(vec![
+        WhereClause::Condition(Condition::new(
+            "target_id".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Integer(target_id)),
+        )?),
+        WhereClause::Condition(Condition::new(
+            "target_type".to_string(),
+            Operator::Eq,
+            Some(SafeValue::Text(
+                target_type.to_string(),
+                ValidationLevel::Standard,
+            )),
+        )?),
+    ]));
+    sql.execute_affected(&builder).await
 }
 
 pub async fn update_field(
@@ -203,7 +231,7 @@ pub async fn update_field(
     field_type: FieldType,
     field_key: &str,
     field_value: &str,
-) -> CustomResult<()> {
+) -> CustomResult<u64> {
     let mut builder = builder::QueryBuilder::new(
         SqlOperation::Update,
         sql.table_name("fields"),
@@ -245,22 +273,42 @@ pub async fn update_field(
                 )),
             )?),
         ]));
-    sql.get_db().execute_query(&builder).await?;
-    Ok(())
+    sql.execute_affected(&builder).await
+}
+
+#[derive(Serialize, Debug)]
+pub struct FieldItem {
+    field_type: String,
+    field_key: String,
+    field_value: Value,
+}
+
+// 写操作统一返回受影响的行数和写入后的字段，字段格式与查询接口一致
+#[derive(Serialize, Debug)]
+pub struct FieldsResponse {
+    affected: u64,
+    fields: Vec<FieldItem>,
+}
+
+impl FieldsResponse {
+    fn new(affected: u64, fields: Vec<FieldItem>) -> Json<Self> {
+        Json(Self { affected, fields })
+    }
 }
 
 #[get("/<target_type>/<target_id>")]
 pub async fn get_field_handler(
-    token: SystemToken,
+    _stage: Installed,
+    principal: Principal,
     state: &State<Arc<AppState>>,
     target_type: &str,
     target_id: i64,
 ) -> AppResult<Json<Value>> {
-    let sql = state.sql_get().await.into_app_result()?;
-    let target_type = TargetType::from_str(&target_type).into_app_result()?;
+    principal.require(Scope::FieldsRead)?;
+    let sql = state.sql_get().await?;
+    let target_type = TargetType::from_str(target_type)?;
     let values = get_field(&sql, target_type, target_id)
-        .await
-        .into_app_result()?;
+        .await?;
     Ok(values)
 }
 
@@ -269,112 +317,154 @@ pub async fn get_field_handler(
     data = "<data>",
     format = "application/json"
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn insert_field_handler(
-    token: SystemToken,
+    _stage: Installed,
+    principal: Principal,
     state: &State<Arc<AppState>>,
     target_type: &str,
     target_id: i64,
     field_type: &str,
     field_key: &str,
     data: Json<Value>,
-) -> AppResult<String> {
-    let sql = state.sql_get().await.into_app_result()?;
-    let target_type = TargetType::from_str(&target_type).into_app_result()?;
-    let data_str = data.to_string();
-    let field_type = FieldType::from_str(&field_type).into_app_result()?;
-    insert_fields(
+) -> AppResult<status::Created<Json<FieldsResponse>>> {
+    principal.require(Scope::FieldsWrite)?;
+    let sql = state.sql_get().await?;
+    let target_type = TargetType::from_str(target_type)?;
+    let field_type = FieldType::from_str(field_type)?;
+    // 由主键保证同一字段只插入一次，并发插入时也只有一个请求成功
+    let affected = insert_fields(
         &sql,
         target_type.clone(),
-        target_id.clone(),
+        target_id,
         field_type.clone(),
-        field_key.clone(),
-        &data_str,
+        field_key,
+        &data.to_string(),
     )
     .await
-    .into_app_result()?;
-    Ok(format!(
-        "操作:插入字段\n目标类型:{}\n目标ID:{}\n字段类型:{}\n字段名称:{}\n字段值:{}",
-        target_type, target_id, field_type, field_key, data_str
-    )
-    .to_string())
+    .map_err(|e| match e.kind() {
+        ErrorKind::Conflict => CustomError::localized(ErrorKind::Conflict, "fields.exists"),
+        _ => e,
+    })?;
+    let location = format!("/field/{}/{}", target_type, target_id);
+    Ok(status::Created::new(location).body(FieldsResponse::new(
+        affected,
+        vec![FieldItem {
+            field_type: field_type.to_string(),
+            field_key: field_key.to_string(),
+            field_value: data.into_inner(),
+        }],
+    )))
 }
 
 #[delete("/<target_type>/<target_id>/<field_type>/<field_key>")]
 pub async fn delete_field_handler(
-    token: SystemToken,
+    _stage: Installed,
+    principal: Principal,
     state: &State<Arc<AppState>>,
     target_type: &str,
     target_id: i64,
     field_type: &str,
     field_key: &str,
-) -> AppResult<String> {
-    let sql = state.sql_get().await.into_app_result()?;
-    let target_type = TargetType::from_str(&target_type).into_app_result()?;
-    let field_type = FieldType::from_str(&field_type).into_app_result()?;
-    delete_fields(
-        &sql,
-        target_type.clone(),
-        target_id.clone(),
-        field_type.clone(),
-        field_key.clone(),
-    )
-    .await
-    .into_app_result()?;
-    Ok(format!(
-        "操作:删除单个字段\n目标类型:{}\n目标ID:{}\n字段类型:{}\n字段名称:{}\n",
-        target_type, target_id, field_type, field_key
-    )
-    .to_string())
+) -> AppResult<Json<FieldsResponse>> {
+    principal.require(Scope::FieldsWrite)?;
+    let sql = state.sql_get().await?;
+    let target_type = TargetType::from_str(target_type)?;
+    let field_type = FieldType::from_str(field_type)?;
+    let affected = delete_fields(&sql, target_type, target_id, field_type, field_key).await?;
+    if affected == 0 {
+        return Err(CustomError::localized(ErrorKind::NotFound, "fields.not_found"));
+    }
+    Ok(FieldsResponse::new(affected, Vec::new()))
 }
+
 #[delete("/<target_type>/<target_id>")]
 pub async fn delete_all_fields_handler(
-    token: SystemToken,
+    _stage: Installed,
+    principal: Principal,
     state: &State<Arc<AppState>>,
     target_type: &str,
     target_id: i64,
-) -> AppResult<String> {
-    let sql = state.sql_get().await.into_app_result()?;
-    let target_type = TargetType::from_str(&target_type).into_app_result()?;
-    delete_all_fields(&sql, target_type.clone(), target_id.clone())
-        .await
-        .into_app_result()?;
-    Ok(format!(
-        "操作:删除所有字段\n目标类型:{}\n目标ID:{}\n",
-        target_type, target_id
-    )
-    .to_string())
+) -> AppResult<Json<FieldsResponse>> {
+    principal.require(Scope::FieldsWrite)?;
+    let sql = state.sql_get().await?;
+    let target_type = TargetType::from_str(target_type)?;
+    let affected = delete_all_fields(&sql, target_type, target_id).await?;
+    if affected == 0 {
+        return Err(CustomError::localized(ErrorKind::NotFound, "fields.not_found"));
+    }
+    Ok(FieldsResponse::new(affected, Vec::new()))
 }
+
 #[put(
     "/<target_type>/<target_id>/<field_type>/<field_key>",
     data = "<data>",
     format = "application/json"
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_field_handler(
-    token: SystemToken,
+    _stage: Installed,
+    principal: Principal,
     state: &State<Arc<AppState>>,
     target_type: &str,
     target_id: i64,
     field_type: &str,
     field_key: &str,
     data: Json<Value>,
-) -> AppResult<String> {
-    let sql = state.sql_get().await.into_app_result()?;
-    let target_type = TargetType::from_str(&target_type).into_app_result()?;
-    let data_str = data.to_string();
-    let field_type = FieldType::from_str(&field_type).into_app_result()?;
-    update_field(
+) -> AppResult<Json<FieldsResponse>> {
+    principal.require(Scope::FieldsWrite)?;
+    let sql = state.sql_get().await?;
+    let target_type = TargetType::from_str(target_type)?;
+    let field_type = FieldType::from_str(field_type)?;
+    let affected = update_field(
         &sql,
-        target_type.clone(),
-        target_id.clone(),
+        target_type,
+        target_id,
         field_type.clone(),
-        field_key.clone(),
-        &data_str,
-    )
-    .await
-    .into_app_result()?;
-    Ok(format!(
-        "操作:更新字段\n目标类型:{}\n目标ID:{}\n字段类型:{}\n字段名称:{}\n字段值:{}",
-        target_type, target_id, field_type, field_key, data_str
+        field_key,
+        &data.to_string(),
     )
-    .to_string())
+    .await?;
+    if affected == 0 {
+        return Err(CustomError::localized(ErrorKind::NotFound, "fields.not_found"));
+    }
+    Ok(FieldsResponse::new(
+        affected,
+        vec![FieldItem {
+            field_type: field_type.to_string(),
+            field_key: field_key.to_string(),
+            field_value: data.into_inner(),
+        }],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::testing::{self, TestSite};
+    use crate::api::users::Role;
+    use rocket::http::{ContentType, Header, Status};
+
+    async fn insert(site: &TestSite, value: &str) -> Status {
+        site.client
+            .post("/field/theme/1/data/color")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", site.bearer("alice")))
+            .body(serde_json::json!(value).to_string())
+            .dispatch()
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn duplicate_field_is_a_conflict() {
+        let site = testing::site(|_| {}).await;
+        site.add_user("alice", "alice@example.com", Role::Administrator)
+            .await;
+
+        let (first, second) = tokio::join!(insert(&site, "red"), insert(&site, "blue"));
+        let mut statuses = vec![first, second];
+        statuses.sort_by_key(|status| status.code);
+        assert_eq!(statuses, vec![Status::Created, Status::Conflict]);
+        assert_eq!(insert(&site, "green").await, Status::Conflict);
+    }
 }
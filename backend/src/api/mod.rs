@@ -1,15 +1,82 @@
+pub mod admin;
+pub mod api_keys;
 pub mod auth;
 pub mod fields;
 pub mod page;
 pub mod post;
 pub mod setup;
 pub mod users;
+#[cfg(test)]
+pub mod testing;
 
 use rocket::request::{FromRequest, Outcome, Request};
-use rocket::routes;
+use rocket::serde::json::Json;
+use rocket::{catch, catchers, routes};
+use crate::api::api_keys::Scope;
 use crate::api::users::Role;
+use crate::common::config;
+use crate::common::i18n::Locale;
+use crate::common::error::{AppResult, CustomError, ErrorBody, ErrorKind};
 use rocket::http::Status;
-use crate::security::jwt;
+use crate::security::{install, jwt};
+use crate::AppState;
+use std::sync::Arc;
+
+// 安装阶段守卫，阶段不符时转发为 404，效果等同于路由未挂载，完成安装后无需重启即可切换路由
+fn stage_outcome<T>(matched: bool, stage: T) -> Outcome<T, ()> {
+    if matched {
+        Outcome::Success(stage)
+    } else {
+        Outcome::Forward(Status::NotFound)
+    }
+}
+
+pub struct SqlStage;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SqlStage {
+    type Error = ();
+    async fn from_request(_request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let init = &config::Config::current().init;
+        stage_outcome(!init.sql, SqlStage)
+    }
+}
+
+pub struct AccountStage;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AccountStage {
+    type Error = ();
+    async fn from_request(_request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let init = &config::Config::current().init;
+        stage_outcome(init.sql && !init.administrator, AccountStage)
+    }
+}
+
+pub struct Installed;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Installed {
+    type Error = ();
+    async fn from_request(_request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let init = &config::Config::current().init;
+        stage_outcome(init.sql && init.administrator, Installed)
+    }
+}
+
+// 安装接口需要携带启动时生成的一次性令牌，防止他人抢先完成安装
+pub struct InstallToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for InstallToken {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one("X-Install-Token") {
+            Some(token) if install::verify_token(token) => Outcome::Success(InstallToken),
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
 
 pub struct Token(String);
 
@@ -31,10 +98,75 @@ impl<'r> FromRequest<'r> for Token {
 }
 
 
-pub struct SystemToken(String);
+// 调用方身份，Authorization 中可以是 JWT 或 API 密钥
+pub enum Principal {
+    User(jwt::CustomClaims),
+    ApiKey(api_keys::ApiKey),
+}
+
+impl Principal {
+    // 管理员令牌拥有全部权限，API 密钥按创建时授予的范围判断
+    pub fn require(&self, scope: Scope) -> AppResult<()> {
+        let allowed = match self {
+            Principal::User(claims) => claims.role == Role::Administrator.to_string(),
+            Principal::ApiKey(key) => key.allows(scope),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(CustomError::localized(ErrorKind::Forbidden, "auth.missing_scope").with_arg("scope", scope))
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Principal {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(token) = request
+            .headers()
+            .get_one("Authorization")
+            .map(|value| value.replace("Bearer ", ""))
+        else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+
+        if !token.starts_with(api_keys::KEY_PREFIX) {
+            // 等待两步验证的临时令牌不能访问其他接口
+            return match jwt::validate_jwt(&token) {
+                Ok(claims) if claims.role != auth::mfa::MFA_PENDING_ROLE => {
+                    Outcome::Success(Principal::User(claims))
+                }
+                _ => Outcome::Error((Status::Unauthorized, ())),
+            };
+        }
+
+        let Some(state) = request.rocket().state::<Arc<AppState>>() else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+        let Ok(sql) = state.sql_get().await else {
+            return Outcome::Error((Status::ServiceUnavailable, ()));
+        };
+        match api_keys::authenticate(&sql, &token).await {
+            Ok(key) => {
+                // 最后使用时间在后台更新，不阻塞请求
+                let id = key.id.clone();
+                state
+                    .spawn_job(async move {
+                        let _ = api_keys::touch(&sql, &id).await;
+                    })
+                    .await;
+                Outcome::Success(Principal::ApiKey(key))
+            }
+            Err(_) => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+pub struct AdminToken(String);
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for SystemToken {
+impl<'r> FromRequest<'r> for AdminToken {
     type Error = ();
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let token = request
@@ -42,17 +174,77 @@ impl<'r> FromRequest<'r> for SystemToken {
             .get_one("Authorization")
             .map(|value| value.replace("Bearer ", ""));
         match token.and_then(|t| jwt::validate_jwt(&t).ok()) {
-            Some(claims) if claims.name == "system" => Outcome::Success(SystemToken(claims.name)),
+            Some(claims) if claims.role == Role::Administrator.to_string() => {
+                Outcome::Success(AdminToken(claims.name))
+            }
             _ => Outcome::Error((Status::Unauthorized, ())),
         }
     }
 }
 
 
+pub fn setup_routes() -> Vec<rocket::Route> {
+    routes![setup::get_step, setup::test_sql, setup::setup_sql, setup::setup_account]
+}
+
 pub fn jwt_routes() -> Vec<rocket::Route> {
-    routes![auth::token::token_system,auth::token::test_token]
+    routes![auth::token::token_system, auth::mfa::token_mfa]
+}
+
+pub fn password_routes() -> Vec<rocket::Route> {
+    routes![auth::email::forgot_handler, auth::email::reset_handler]
+}
+
+pub fn email_routes() -> Vec<rocket::Route> {
+    routes![
+        auth::email::send_verification_handler,
+        auth::email::verify_handler
+    ]
+}
+
+pub fn oidc_routes() -> Vec<rocket::Route> {
+    routes![
+        auth::oidc::providers_handler,
+        auth::oidc::authorize_handler,
+        auth::oidc::link_handler,
+        auth::oidc::callback_handler,
+        auth::oidc::unlink_handler
+    ]
+}
+
+pub fn mfa_routes() -> Vec<rocket::Route> {
+    routes![
+        auth::mfa::enroll_handler,
+        auth::mfa::confirm_handler,
+        auth::mfa::disable_handler
+    ]
+}
+
+// 仅在开发模式下挂载
+pub fn debug_routes() -> Vec<rocket::Route> {
+    routes![auth::token::test_token]
 }
 
 pub fn fields_routes() -> Vec<rocket::Route> {
     routes![fields::get_field_handler,fields::insert_field_handler,fields::delete_field_handler,fields::delete_all_fields_handler,fields::update_field_handler]
 }
+
+pub fn admin_routes() -> Vec<rocket::Route> {
+    routes![
+        admin::schema_drift_handler,
+        admin::list_api_keys_handler,
+        admin::create_api_key_handler,
+        admin::revoke_api_key_handler
+    ]
+}
+
+// 守卫失败、路由不存在或请求体无法解析时没有处理函数的返回值，统一返回 JSON 错误
+#[catch(default)]
+fn default_catcher(status: Status, request: &Request) -> (Status, Json<ErrorBody>) {
+    let kind = ErrorKind::from_status(status);
+    (status, Json(ErrorBody::new(kind, kind.default_message(Locale::of(request)))))
+}
+
+pub fn catchers() -> Vec<rocket::Catcher> {
+    catchers![default_catcher]
+}
@@ -1,157 +1,203 @@
 use super::fields::{FieldType, TargetType};
 use super::users::Role;
-use super::{fields, users};
+use super::api_keys::{self, Scope};
+use super::{fields, users, AccountStage, InstallToken, SqlStage};
 use crate::common::config;
-use crate::common::error::{AppResult, AppResultInto};
-use crate::common::helpers;
+use crate::common::error::{AppResult, CustomError, CustomResult, ErrorKind};
+use crate::common::i18n::Locale;
 use crate::security;
 use crate::storage::sql;
 use crate::AppState;
 use chrono::Duration;
-use rocket::{http::Status,get, post, response::status, serde::json::Json, State};
+use rocket::{get, post, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::sync::Arc;
-use std::sync::OnceLock;
-
-
-static STEP: OnceLock<u8> = OnceLock::new();
 
 
 #[get("/step")]
 pub async fn get_step() -> String {
-    STEP.get_or_init(|| {
-        let config = config::Config::read().unwrap_or_else(|e| {
-            eprintln!("配置读取失败: {}", e);
-            config::Config::default()
-        });
-        
-        if !config.init.sql {
-            1
-        } else if !config.init.administrator {
-            2
-        } else {
-            3
-        }
-    }).to_string()
+    let init = &config::Config::current().init;
+    if !init.sql {
+        "1".to_string()
+    } else if !init.administrator {
+        "2".to_string()
+    } else {
+        "3".to_string()
+    }
 }
 
+// 只测试连接和权限，不写入配置也不创建数据库
+#[post("/sql/test", format = "application/json", data = "<sql_config>")]
+pub async fn test_sql(
+    _stage: SqlStage,
+    _token: InstallToken,
+    sql_config: Json<config::SqlConfig>,
+    locale: Locale,
+) -> Json<sql::ProbeReport> {
+    Json(sql::Database::probe(&sql_config, locale).await)
+}
 
-#[post("/sql", format = "application/json", data = "<sql_config>")]
+// 数据库中已有同前缀的表时默认拒绝安装，overwrite=true 时删除后重建
+#[post("/sql?<overwrite>", format = "application/json", data = "<sql_config>")]
 pub async fn setup_sql(
+    _stage: SqlStage,
+    _token: InstallToken,
+    overwrite: Option<bool>,
     sql_config: Json<config::SqlConfig>,
     state: &State<Arc<AppState>>,
+    locale: Locale,
 ) -> AppResult<String> {
-    let mut config = config::Config::read().unwrap_or_default();
+    let config = config::Config::read().unwrap_or_default();
     if config.init.sql {
-        return Err(status::Custom(
-            Status::BadRequest,
-            "数据库已经初始化".to_string(),
-        ));
+        return Err(CustomError::localized(ErrorKind::Validation, "setup.already_installed"));
     }
 
     let sql_config = sql_config.into_inner();
+    sql_config
+        .validate()
+        .map_err(|e| e.with_kind(ErrorKind::Validation))?;
+
+    install_sql(&sql_config, sql_config.clone(), overwrite.unwrap_or(false))
+        .await?;
+    state.reconfigure().await?;
+    Ok(locale.text("setup.installed"))
+}
+
+// 安装向导和命令行安装共用，用 sql_config 建库，成功后把 persisted 写入配置文件；
+// persisted 不含环境变量叠加的值，避免把 DATABASE_URL 中的密码等写进文件
+pub async fn install_sql(
+    sql_config: &config::SqlConfig,
+    persisted: config::SqlConfig,
+    overwrite: bool,
+) -> CustomResult<()> {
+    sql::Database::initial_setup(sql_config.clone(), overwrite).await?;
 
+    let mut config = config::Config::read().unwrap_or_default();
     config.init.sql = true;
-    config.sql_config = sql_config.clone();
-    sql::Database::initial_setup(sql_config.clone())
-        .await
-        .into_app_result()?;
-
-    config::Config::write(config).into_app_result()?;
-    state.trigger_restart().await.into_app_result()?;
-    Ok("Database installation successful".to_string())
+    config.sql_config = persisted;
+    config::Config::write(config)
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct StepAccountData {
-    username: String,
-    email: String,
-    password: String,
+    pub username: String,
+    pub email: String,
+    pub password: String,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct StepAccountResponse {
-    token: String,
-    username: String,
-    password: String,
+    pub token: String,
+    pub api_key: String,
 }
 
-#[post("/administrator", format = "application/json", data = "<data>")]
-pub async fn setup_account(
-    data: Json<StepAccountData>,
-    state: &State<Arc<AppState>>,
-) -> AppResult<Json<StepAccountResponse>> {
-    let mut config = config::Config::read().unwrap_or_default();
-    if config.init.administrator {
-        return Err(status::Custom(
-            Status::BadRequest,
-            "管理员用户已设置".to_string(),
-        ));
-    }
+// 前端渲染站点时使用的 API 密钥
+const FRONTEND_KEY_NAME: &str = "frontend";
+const FRONTEND_KEY_SCOPES: [Scope; 2] = [Scope::FieldsRead, Scope::FieldsWrite];
 
-    security::jwt::generate_key().into_app_result()?;
+// 记录本次安装新建的内容，失败时撤销，之前已存在的内容保持不变
+#[derive(Default)]
+struct AccountSetup {
+    key_generated: bool,
+    users: Vec<String>,
+    // 被本次安装更新的账户原始数据
+    updated_users: Vec<users::UserSnapshot>,
+    api_keys: Vec<String>,
+    fields: Vec<(FieldType, &'static str)>,
+}
 
-    let data = data.into_inner();
+impl AccountSetup {
+    async fn rollback(self, sql: &sql::Database) {
+        for (field_type, field_key) in self.fields {
+            let _ = fields::delete_fields(sql, TargetType::System, 0, field_type, field_key).await;
+        }
+        for id in self.api_keys {
+            let _ = api_keys::delete_key(sql, &id).await;
+        }
+        for username in self.users {
+            let _ = users::delete_user(sql, &username).await;
+        }
+        for original in self.updated_users {
+            let _ = users::restore_user(sql, &original).await;
+        }
+        if self.key_generated {
+            let _ = security::jwt::remove_key();
+        }
+    }
+}
 
-    let sql = {
-        state.sql_link(&config.sql_config).await.into_app_result()?;
-        state.sql_get().await.into_app_result()?
-    };
+async fn system_field_exists(sql: &sql::Database, field_key: &str) -> CustomResult<bool> {
+    let values = fields::get_field(sql, TargetType::System, 0).await?;
+    Ok(values.as_array().is_some_and(|rows| {
+        rows.iter()
+            .any(|row| row.get("field_key").and_then(|key| key.as_str()) == Some(field_key))
+    }))
+}
 
+async fn create_account(
+    sql: &sql::Database,
+    data: StepAccountData,
+    overwrite: bool,
+    setup: &mut AccountSetup,
+) -> CustomResult<StepAccountResponse> {
+    if !security::jwt::key_exists()? {
+        security::jwt::generate_key()?;
+        setup.key_generated = true;
+    }
 
-    users::insert_user(
-        &sql,
-        users::RegisterData {
-            username: data.username.clone(),
-            email: data.email,
-            password: data.password,
-            role: Role::Administrator,
-        },
-    )
-    .await
-    .into_app_result()?;
-
-    let system_credentials = (
-        helpers::generate_random_string(20),
-        helpers::generate_random_string(20),
-    );
-
-    let system_account = users::RegisterData {
-        username: system_credentials.0.clone(),
-        email: "author@lsy22.com".to_string(),
-        password: system_credentials.1.clone(),
+    let administrator = users::RegisterData {
+        username: data.username.clone(),
+        email: data.email,
+        password: data.password,
         role: Role::Administrator,
     };
+    // 已有同名账户时拒绝，重装时接管上次中断安装留下的管理员需要显式指定 overwrite
+    match users::snapshot_user(sql, &data.username).await? {
+        None => {
+            users::insert_user(sql, administrator).await?;
+            setup.users.push(data.username.clone());
+        }
+        Some(original) if overwrite && original.role == Role::Administrator.to_string() => {
+            setup.updated_users.push(original);
+            users::update_user(sql, administrator).await?;
+        }
+        Some(_) => {
+            return Err(
+                CustomError::localized(ErrorKind::Conflict, "setup.username_taken")
+                    .with_arg("username", data.username),
+            )
+        }
+    }
 
-    users::insert_user(
-        &sql,
-        system_account, 
-    )
-    .await
-    .into_app_result()?;
-
-    fields::insert_fields(
-        &sql,
-        TargetType::System,
+    let api_key = api_keys::create_key(
+        sql,
+        FRONTEND_KEY_NAME,
+        &FRONTEND_KEY_SCOPES,
         0,
-        FieldType::Meta,
-        "keywords",
-        "echoes,blog,个人博客",
+        &data.username,
     )
-    .await
-    .into_app_result()?;
+    .await?;
+    setup.api_keys.push(api_key.id);
 
-    fields::insert_fields(
-        &sql,
-        TargetType::System,
-        0,
-        FieldType::Data,
-        "current_theme",
-        "echoes",
-    )
-    .await
-    .into_app_result()?;
+    for (field_type, field_key, field_value) in [
+        (FieldType::Meta, "keywords", "echoes,blog,个人博客"),
+        (FieldType::Data, "current_theme", "echoes"),
+    ] {
+        if system_field_exists(sql, field_key).await? {
+            continue;
+        }
+        fields::insert_fields(
+            sql,
+            TargetType::System,
+            0,
+            field_type.clone(),
+            field_key,
+            field_value,
+        )
+        .await?;
+        setup.fields.push((field_type, field_key));
+    }
 
     let token = security::jwt::generate_jwt(
         security::jwt::CustomClaims {
@@ -159,15 +205,96 @@ pub async fn setup_account(
             role: Role::Administrator.to_string(),
         },
         Duration::days(7),
-    )
-    .into_app_result()?;
+    )?;
+
+    let mut config = config::Config::read().unwrap_or_default();
     config.init.administrator = true;
-    config::Config::write(config).into_app_result()?;
-    state.trigger_restart().await.into_app_result()?;
+    config::Config::write(config)?;
 
-    Ok(Json(StepAccountResponse {
+    Ok(StepAccountResponse {
         token,
-        username: system_credentials.0,
-        password: system_credentials.1,
-    }))
-}
\ No newline at end of file
+        api_key: api_key.key,
+    })
+}
+
+// 已有同名管理员时默认拒绝，overwrite=true 时按本次提交的信息更新该账户
+#[post("/administrator?<overwrite>", format = "application/json", data = "<data>")]
+pub async fn setup_account(
+    _stage: AccountStage,
+    _token: InstallToken,
+    overwrite: Option<bool>,
+    data: Json<StepAccountData>,
+    state: &State<Arc<AppState>>,
+) -> AppResult<Json<StepAccountResponse>> {
+    let config = config::Config::read().unwrap_or_default();
+    if config.init.administrator {
+        return Err(CustomError::localized(ErrorKind::Validation, "setup.administrator_exists"));
+    }
+    security::password::check_policy(&data.password)?;
+
+    let sql = state.sql_get().await?;
+    let response = install_account(&sql, data.into_inner(), overwrite.unwrap_or(false))
+        .await?;
+    state.reconfigure().await?;
+
+    Ok(Json(response))
+}
+
+// 失败时撤销本次写入的内容，成功后安装令牌作废
+pub async fn install_account(
+    sql: &sql::Database,
+    data: StepAccountData,
+    overwrite: bool,
+) -> CustomResult<StepAccountResponse> {
+    let mut setup = AccountSetup::default();
+    match create_account(sql, data, overwrite, &mut setup).await {
+        Ok(response) => {
+            security::install::remove_token()?;
+            Ok(response)
+        }
+        Err(e) => {
+            setup.rollback(sql).await;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing;
+
+    fn account(email: &str) -> StepAccountData {
+        StepAccountData {
+            username: "alice".to_string(),
+            email: email.to_string(),
+            password: "another-strong-pass".to_string(),
+        }
+    }
+
+    async fn email_of(sql: &sql::Database) -> String {
+        users::snapshot_user(sql, "alice")
+            .await
+            .unwrap()
+            .unwrap()
+            .email
+    }
+
+    #[tokio::test]
+    async fn existing_administrator_needs_overwrite() {
+        let site = testing::site(|_| {}).await;
+        site.add_user("alice", "alice@example.com", Role::Administrator)
+            .await;
+
+        let taken = install_account(&site.sql, account("mallory@example.com"), false)
+            .await
+            .unwrap_err();
+        assert_eq!(taken.kind(), ErrorKind::Conflict);
+        assert_eq!(email_of(&site.sql).await, "alice@example.com");
+
+        install_account(&site.sql, account("admin@example.com"), true)
+            .await
+            .unwrap();
+        assert_eq!(email_of(&site.sql).await, "admin@example.com");
+    }
+}
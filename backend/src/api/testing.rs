@@ -0,0 +1,116 @@
+use super::users::{self, RegisterData, Role};
+use crate::common::config;
+use crate::security::jwt;
+use crate::storage::sql::{self, memory_database};
+use crate::AppState;
+use chrono::Duration;
+use rocket::local::asynchronous::Client;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{Mutex, MutexGuard};
+
+// 运行中的配置是全局的，修改配置的接口测试依次执行
+static SITE_LOCK: Mutex<()> = Mutex::const_new(());
+static WORKDIR: OnceLock<PathBuf> = OnceLock::new();
+
+// 密钥、邮件等按工作目录存放，测试进程切换到临时目录，避免写入仓库
+fn workdir() -> &'static PathBuf {
+    WORKDIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("echoes-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("测试目录创建失败");
+        std::env::set_current_dir(&dir).expect("测试目录切换失败");
+        jwt::generate_key().expect("测试密钥生成失败");
+        dir
+    })
+}
+
+// 已完成安装的站点，数据库为独立的内存数据库
+pub struct TestSite {
+    pub client: Client,
+    pub sql: sql::Database,
+    pub dir: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+pub async fn site(configure: impl FnOnce(&mut config::Config)) -> TestSite {
+    let lock = SITE_LOCK.lock().await;
+    let dir = workdir().join(crate::common::helpers::generate_random_string(8));
+    std::fs::create_dir_all(&dir).expect("测试目录创建失败");
+
+    let mut config = config::Config::default();
+    config.init.sql = true;
+    config.init.administrator = true;
+    config.mail.backend = "capture".to_string();
+    config.mail.from = "Echoes <noreply@example.com>".to_string();
+    config.mail.capture_dir = dir.join("mail").display().to_string();
+    configure(&mut config);
+    config::Config::set_current(config);
+
+    let sql = memory_database().await;
+    sql.migrate(false).await.expect("迁移失败");
+    let state = Arc::new(AppState::new());
+    *state.db.lock().await = Some(sql.clone());
+
+    let client = Client::tracked(crate::mount(rocket::build().manage(state)))
+        .await
+        .expect("测试服务启动失败");
+    TestSite {
+        client,
+        sql,
+        dir,
+        _lock: lock,
+    }
+}
+
+impl TestSite {
+    pub async fn add_user(&self, username: &str, email: &str, role: Role) {
+        users::insert_user(
+            &self.sql,
+            RegisterData {
+                username: username.to_string(),
+                email: email.to_string(),
+                password: "correct-horse-battery".to_string(),
+                role,
+            },
+        )
+        .await
+        .expect("测试用户创建失败");
+    }
+
+    pub fn bearer(&self, username: &str) -> String {
+        let token = jwt::generate_jwt(
+            jwt::CustomClaims {
+                name: username.to_string(),
+                role: Role::Administrator.to_string(),
+            },
+            Duration::minutes(5),
+        )
+        .expect("测试令牌签发失败");
+        format!("Bearer {}", token)
+    }
+
+    // 邮件在后台发送，等待捕获目录中出现第 count 封邮件
+    pub async fn mails(&self, count: usize) -> Vec<serde_json::Value> {
+        let dir = self.dir.join("mail");
+        for _ in 0..100 {
+            let mut names = std::fs::read_dir(&dir)
+                .map(|entries| {
+                    entries
+                        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            if names.len() >= count {
+                names.sort();
+                return names
+                    .iter()
+                    .map(|path| {
+                        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+                    })
+                    .collect();
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("没有收到邮件");
+    }
+}
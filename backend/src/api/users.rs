@@ -1,9 +1,11 @@
-use crate::common::error::{CustomErrorInto, CustomResult};
-use crate::security::bcrypt;
+use crate::common::error::{CustomError, CustomResult, ErrorKind};
+use crate::security::password;
 use crate::storage::{sql, sql::builder};
 use regex::Regex;
 use rocket::{get, http::Status, post, response::status, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 
 #[derive(Deserialize, Serialize)]
@@ -27,6 +29,16 @@ impl Display for Role {
     }
 }
 
+impl Role {
+    pub fn from_str(s: &str) -> CustomResult<Self> {
+        match s.to_lowercase().as_str() {
+            "administrator" => Ok(Role::Administrator),
+            "visitor" => Ok(Role::Visitor),
+            _ => Err(CustomError::localized(ErrorKind::Validation, "user.invalid_role")),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RegisterData {
     pub username: String,
@@ -35,14 +47,20 @@ pub struct RegisterData {
     pub role: Role,
 }
 
-pub async fn insert_user(sql: &sql::Database, data: RegisterData) -> CustomResult<()> {
-    let password_hash = bcrypt::generate_hash(&data.password)?;
-
+pub fn check_email(email: &str) -> CustomResult<()> {
     let re = Regex::new(r"([a-zA-Z0-9._-]+@[a-zA-Z0-9._-]+\.[a-zA-Z0-9_-]+)")?;
 
-    if false == re.is_match(&data.email) {
-        return Err("邮箱格式不正确".into_custom_error());
+    if false == re.is_match(email) {
+        return Err(CustomError::localized(ErrorKind::Validation, "user.invalid_email"));
     }
+    Ok(())
+}
+
+pub async fn insert_user(sql: &sql::Database, data: RegisterData) -> CustomResult<()> {
+    password::check_policy(&data.password)?;
+    let password_hash = password::generate_hash(&data.password)?;
+
+    check_email(&data.email)?;
 
     let mut builder = builder::QueryBuilder::new(
         builder::SqlOperation::Insert,
@@ -67,14 +85,289 @@ pub async fn insert_user(sql: &sql::Database, data: RegisterData) -> CustomResul
             builder::SafeValue::Text(data.role.to_string(), builder::ValidationLevel::Strict),
         )?;
 
-    sql.get_db().execute_query(&builder).await?;
+    sql.execute_query(&builder).await?;
     Ok(())
 }
 
-pub fn delete() {}
+fn text_condition(field: &str, value: &str) -> CustomResult<builder::WhereClause> {
+    Ok(builder::WhereClause::Condition(builder::Condition::new(
+        field.to_string(),
+        builder::Operator::Eq,
+        Some(builder::SafeValue::Text(
+            value.to_string(),
+            builder::ValidationLevel::Standard,
+        )),
+    )?))
+}
+
+fn username_condition(username: &str) -> CustomResult<builder::WhereClause> {
+    text_condition("username", username)
+}
+
+async fn delete_where(sql: &sql::Database, condition: builder::WhereClause) -> CustomResult<()> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Delete,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder.add_condition(condition);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
 
-pub fn update() {}
+pub async fn delete_user(sql: &sql::Database, username: &str) -> CustomResult<()> {
+    delete_where(sql, username_condition(username)?).await
+}
 
-pub fn select() {}
+// 更新邮箱、密码和角色，用户名不变，邮箱验证状态会被清除
+pub async fn update_user(sql: &sql::Database, data: RegisterData) -> CustomResult<()> {
+    password::check_policy(&data.password)?;
+    let password_hash = password::generate_hash(&data.password)?;
+    check_email(&data.email)?;
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Update,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value(
+            "email".to_string(),
+            builder::SafeValue::Text(data.email, builder::ValidationLevel::Standard),
+        )?
+        .set_value(
+            "password_hash".to_string(),
+            builder::SafeValue::Text(password_hash, builder::ValidationLevel::Relaxed),
+        )?
+        .set_value(
+            "role".to_string(),
+            builder::SafeValue::Text(data.role.to_string(), builder::ValidationLevel::Strict),
+        )?
+        // 邮箱可能已修改，需要重新验证
+        .set_value(
+            "email_verified_at".to_string(),
+            builder::SafeValue::Integer(0),
+        )?
+        .add_condition(username_condition(&data.username)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+// update_user 会覆盖的字段，用于撤销修改
+#[derive(Debug)]
+pub struct UserSnapshot {
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+    pub role: String,
+    pub email_verified_at: i64,
+}
+
+pub async fn snapshot_user(
+    sql: &sql::Database,
+    username: &str,
+) -> CustomResult<Option<UserSnapshot>> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Select,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .add_field("email".to_string())?
+        .add_field("password_hash".to_string())?
+        .add_field("role".to_string())?
+        .add_field("email_verified_at".to_string())?
+        .add_condition(username_condition(username)?);
+    Ok(sql.primary().execute_query(&builder).await?.first().map(|row| {
+        let text = |key: &str| {
+            row.get(key)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        UserSnapshot {
+            username: username.to_string(),
+            email: text("email"),
+            password_hash: text("password_hash"),
+            role: text("role"),
+            email_verified_at: row
+                .get("email_verified_at")
+                .and_then(|value| value.as_i64())
+                .unwrap_or(0),
+        }
+    }))
+}
+
+pub async fn restore_user(sql: &sql::Database, snapshot: &UserSnapshot) -> CustomResult<()> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Update,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value(
+            "email".to_string(),
+            builder::SafeValue::Text(snapshot.email.clone(), builder::ValidationLevel::Standard),
+        )?
+        .set_value(
+            "password_hash".to_string(),
+            builder::SafeValue::Text(
+                snapshot.password_hash.clone(),
+                builder::ValidationLevel::Relaxed,
+            ),
+        )?
+        .set_value(
+            "role".to_string(),
+            builder::SafeValue::Text(snapshot.role.clone(), builder::ValidationLevel::Strict),
+        )?
+        .set_value(
+            "email_verified_at".to_string(),
+            builder::SafeValue::Integer(snapshot.email_verified_at),
+        )?
+        .add_condition(username_condition(&snapshot.username)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+pub async fn user_exists(sql: &sql::Database, username: &str) -> CustomResult<bool> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Select,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .add_field("username".to_string())?
+        .add_condition(username_condition(username)?);
+    Ok(!sql.primary().execute_query(&builder).await?.is_empty())
+}
+
+// 只修改密码，用于找回管理员账户
+pub async fn set_password(sql: &sql::Database, username: &str, new_password: &str) -> CustomResult<()> {
+    if !user_exists(sql, username).await? {
+        return Err(CustomError::localized(ErrorKind::NotFound, "user.not_found_named").with_arg("username", username));
+    }
+    password::check_policy(new_password)?;
+    store_hash(sql, username, &password::generate_hash(new_password)?).await
+}
+
+// 登录成功后按当前哈希参数重新计算，不检查密码规则
+pub async fn rehash_password(sql: &sql::Database, username: &str, current: &str) -> CustomResult<()> {
+    store_hash(sql, username, &password::generate_hash(current)?).await
+}
+
+async fn store_hash(sql: &sql::Database, username: &str, password_hash: &str) -> CustomResult<()> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Update,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value(
+            "password_hash".to_string(),
+            builder::SafeValue::Text(password_hash.to_string(), builder::ValidationLevel::Relaxed),
+        )?
+        .add_condition(username_condition(username)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+// 按用户名或邮箱查找，返回 (用户名, 邮箱, 邮箱验证时间)
+async fn find_contact(
+    sql: &sql::Database,
+    condition: builder::WhereClause,
+) -> CustomResult<Option<(String, String, i64)>> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Select,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .add_field("username".to_string())?
+        .add_field("email".to_string())?
+        .add_field("email_verified_at".to_string())?
+        .add_condition(condition);
+    Ok(sql.primary().execute_query(&builder).await?.first().map(|row| {
+        let text = |key: &str| {
+            row.get(key)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        (
+            text("username"),
+            text("email"),
+            row.get("email_verified_at")
+                .and_then(|value| value.as_i64())
+                .unwrap_or(0),
+        )
+    }))
+}
+
+pub async fn contact_by_username(
+    sql: &sql::Database,
+    username: &str,
+) -> CustomResult<Option<(String, String, i64)>> {
+    find_contact(sql, username_condition(username)?).await
+}
+
+pub async fn contact_by_email(
+    sql: &sql::Database,
+    email: &str,
+) -> CustomResult<Option<(String, String, i64)>> {
+    find_contact(sql, text_condition("email", email)?).await
+}
+
+pub async fn role_of(sql: &sql::Database, username: &str) -> CustomResult<Option<String>> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Select,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .add_field("role".to_string())?
+        .add_condition(username_condition(username)?);
+    Ok(sql
+        .primary()
+        .execute_query(&builder)
+        .await?
+        .first()
+        .and_then(|row| row.get("role"))
+        .and_then(|value| value.as_str())
+        .map(String::from))
+}
+
+pub async fn set_email_verified(sql: &sql::Database, username: &str, at: i64) -> CustomResult<()> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Update,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .set_value(
+            "email_verified_at".to_string(),
+            builder::SafeValue::Integer(at),
+        )?
+        .add_condition(username_condition(username)?);
+    sql.execute_query(&builder).await?;
+    Ok(())
+}
+
+pub async fn list_users(sql: &sql::Database) -> CustomResult<Vec<HashMap<String, Value>>> {
+    let mut builder = builder::QueryBuilder::new(
+        builder::SqlOperation::Select,
+        sql.table_name("users"),
+        sql.get_type(),
+    )?;
+    builder
+        .add_field("username".to_string())?
+        .add_field("email".to_string())?
+        .add_field("role".to_string())?
+        .add_field("created_at".to_string())?;
+    let mut users = sql.execute_query(&builder).await?;
+    users.sort_by(|a, b| {
+        let key = |row: &HashMap<String, Value>| row.get("username").map(|v| v.to_string());
+        key(a).cmp(&key(b))
+    });
+    Ok(users)
+}
 
 pub fn check() {}
@@ -0,0 +1,64 @@
+use super::{connect, CommandResult};
+use crate::api::api_keys::{self, Scope};
+use crate::common::cli::ApiKeyCommand;
+use crate::common::error::CustomResult;
+use crate::storage::sql;
+use chrono::{DateTime, Utc};
+
+fn format_time(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(time) if timestamp != 0 => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        _ => "-".to_string(),
+    }
+}
+
+async fn execute(sql: &sql::Database, command: &ApiKeyCommand) -> CustomResult<()> {
+    match command {
+        ApiKeyCommand::Create {
+            name,
+            scopes,
+            expires_in_days,
+        } => {
+            let scopes = scopes
+                .iter()
+                .map(|scope| Scope::from_str(scope))
+                .collect::<CustomResult<Vec<_>>>()?;
+            let expires_at = api_keys::expires_at(*expires_in_days)?;
+            let created = api_keys::create_key(sql, name, &scopes, expires_at, "").await?;
+            eprintln!("API密钥 {} 已创建", created.id);
+            println!("{}", created.key);
+        }
+        ApiKeyCommand::List => {
+            for key in api_keys::list_keys(sql).await? {
+                let state = if key.revoked_at != 0 {
+                    "已吊销"
+                } else if key.expires_at != 0 && key.expires_at <= Utc::now().timestamp() {
+                    "已过期"
+                } else {
+                    "有效"
+                };
+                println!(
+                    "{}\t{}\t{}\t{}\t最后使用: {}\t过期: {}",
+                    key.id,
+                    key.name,
+                    key.scopes.join(","),
+                    state,
+                    format_time(key.last_used_at),
+                    format_time(key.expires_at)
+                );
+            }
+        }
+        ApiKeyCommand::Revoke { id } => {
+            api_keys::revoke_key(sql, id).await?;
+            eprintln!("API密钥 {} 已吊销", id);
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(command: &ApiKeyCommand) -> CommandResult {
+    let sql = connect().await?;
+    let result = execute(&sql, command).await;
+    sql.close().await?;
+    Ok(result?)
+}
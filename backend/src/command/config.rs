@@ -0,0 +1,27 @@
+use super::{CommandError, CommandResult, EXIT_FAILURE};
+use crate::common::cli::ConfigCommand;
+use crate::common::config;
+
+const MASK: &str = "******";
+
+pub fn run(command: &ConfigCommand) -> CommandResult {
+    match command {
+        ConfigCommand::Show { show_secrets } => {
+            let mut config = config::Config::load().map_err(CommandError::usage)?;
+            if !show_secrets && !config.sql_config.password.is_empty() {
+                config.sql_config.password = MASK.to_string();
+            }
+            if !show_secrets && !config.mail.smtp.password.is_empty() {
+                config.mail.smtp.password = MASK.to_string();
+            }
+            for provider in config.oidc.values_mut() {
+                if !show_secrets && !provider.client_secret.is_empty() {
+                    provider.client_secret = MASK.to_string();
+                }
+            }
+            let output = toml::to_string_pretty(&config).map_err(|e| CommandError::new(EXIT_FAILURE, e))?;
+            println!("{}", output);
+        }
+    }
+    Ok(())
+}
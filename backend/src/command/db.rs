@@ -0,0 +1,54 @@
+use super::{connect, CommandResult};
+use crate::common::cli::DbCommand;
+use crate::common::error::CustomResult;
+use crate::storage::sql::{self, introspect, migration};
+
+async fn execute(sql: &sql::Database, command: &DbCommand) -> CustomResult<()> {
+    match command {
+        DbCommand::Migrate { dry_run, rollback } => {
+            let plans = match rollback {
+                Some(target) => sql.rollback(*target, *dry_run).await?,
+                None => sql.migrate(*dry_run).await?,
+            };
+            if plans.is_empty() {
+                eprintln!("没有需要执行的迁移");
+            }
+            for plan in &plans {
+                if *dry_run {
+                    println!("{}\n", plan);
+                } else if rollback.is_some() {
+                    println!("已回滚迁移: {:04} {}", plan.version, plan.name);
+                } else {
+                    println!("已执行迁移: {:04} {}", plan.version, plan.name);
+                }
+            }
+        }
+        DbCommand::Status => {
+            let applied = sql.migrator().applied_versions().await?;
+            for migration in migration::migrations(sql.get_prefix())? {
+                let state = if applied.contains(&migration.version) {
+                    "已执行"
+                } else {
+                    "未执行"
+                };
+                println!("{:04}\t{}\t{}", migration.version, migration.name, state);
+            }
+
+            let report = introspect::detect_drift(sql).await?;
+            if report.drifted {
+                println!("表结构与迁移定义不一致:");
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("表结构与迁移定义一致");
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(command: &DbCommand) -> CommandResult {
+    let sql = connect().await?;
+    let result = execute(&sql, command).await;
+    sql.close().await?;
+    Ok(result?)
+}
@@ -0,0 +1,67 @@
+use super::{connect, CommandResult};
+use crate::api::fields::{self, FieldType, TargetType};
+use crate::common::cli::FieldCommand;
+use crate::common::error::CustomResult;
+use crate::storage::sql;
+use serde_json::Value;
+
+async fn execute(sql: &sql::Database, command: &FieldCommand) -> CustomResult<()> {
+    match command {
+        FieldCommand::Get {
+            target_type,
+            target_id,
+            key,
+        } => {
+            let values = fields::get_field(sql, TargetType::from_str(target_type)?, *target_id)
+                .await?
+                .into_inner();
+            let values = match key {
+                Some(key) => Value::Array(
+                    values
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter(|row| row.get("field_key").and_then(|k| k.as_str()) == Some(key))
+                        .cloned()
+                        .collect(),
+                ),
+                None => values,
+            };
+            println!("{}", serde_json::to_string_pretty(&values)?);
+        }
+        FieldCommand::Set {
+            target_type,
+            target_id,
+            field_type,
+            key,
+            value,
+        } => {
+            let target_type = TargetType::from_str(target_type)?;
+            let field_type = FieldType::from_str(field_type)?;
+            let exists = fields::get_field(sql, target_type.clone(), *target_id)
+                .await?
+                .as_array()
+                .is_some_and(|rows| {
+                    rows.iter().any(|row| {
+                        row.get("field_key").and_then(|k| k.as_str()) == Some(key.as_str())
+                            && row.get("field_type").and_then(|t| t.as_str())
+                                == Some(field_type.to_string().as_str())
+                    })
+                });
+            if exists {
+                fields::update_field(sql, target_type, *target_id, field_type, key, value).await?;
+            } else {
+                fields::insert_fields(sql, target_type, *target_id, field_type, key, value).await?;
+            }
+            eprintln!("字段 {} 已写入", key);
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(command: &FieldCommand) -> CommandResult {
+    let sql = connect().await?;
+    let result = execute(&sql, command).await;
+    sql.close().await?;
+    Ok(result?)
+}
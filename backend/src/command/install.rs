@@ -0,0 +1,139 @@
+use super::{CommandError, CommandResult, EXIT_FAILURE, EXIT_INSTALLED};
+use crate::api::{setup, users};
+use crate::common::cli::InstallArgs;
+use crate::common::config;
+use crate::storage::sql;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Seed {
+    sql_config: Option<config::SqlConfig>,
+    administrator: Option<SeedAdministrator>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct SeedAdministrator {
+    username: Option<String>,
+    email: Option<String>,
+    password: Option<String>,
+}
+
+impl Seed {
+    fn read(path: &Path) -> Result<Self, CommandError> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            CommandError::usage(format!("安装参数文件 {} 读取失败: {}", path.display(), e))
+        })?;
+        toml::from_str(&content).map_err(|e| {
+            CommandError::usage(format!("安装参数文件 {} 解析失败: {}", path.display(), e))
+        })
+    }
+}
+
+fn apply_args(sql_config: &mut config::SqlConfig, args: &InstallArgs) {
+    if let Some(db_type) = &args.db_type {
+        sql_config.db_type = db_type.clone();
+    }
+    if let Some(host) = &args.db_host {
+        sql_config.host = host.clone();
+    }
+    if let Some(port) = args.db_port {
+        sql_config.port = port;
+    }
+    if let Some(user) = &args.db_user {
+        sql_config.user = user.clone();
+    }
+    if let Some(password) = &args.db_password {
+        sql_config.password = password.clone();
+    }
+    if let Some(db_name) = &args.db_name {
+        sql_config.db_name = db_name.clone();
+    }
+    if let Some(db_prefix) = &args.db_prefix {
+        sql_config.db_prefix = db_prefix.clone();
+    }
+}
+
+// 数据库配置按 配置文件 < 安装参数文件 < 环境变量 < 命令行参数 的顺序叠加，
+// 返回 (用于连接的配置, 写入配置文件的配置)，后者不含环境变量
+fn resolve_sql_config(
+    args: &InstallArgs,
+    seed: Option<config::SqlConfig>,
+) -> Result<(config::SqlConfig, config::SqlConfig), CommandError> {
+    let mut persisted = match seed {
+        Some(sql_config) => sql_config,
+        None => config::Config::read().unwrap_or_default().sql_config,
+    };
+    apply_args(&mut persisted, args);
+
+    let mut sql_config = persisted.clone();
+    sql_config.apply_env().map_err(CommandError::usage)?;
+    apply_args(&mut sql_config, args);
+
+    sql_config.validate().map_err(CommandError::usage)?;
+    Ok((sql_config, persisted))
+}
+
+fn resolve_administrator(
+    args: &InstallArgs,
+    seed: Option<SeedAdministrator>,
+) -> Result<setup::StepAccountData, CommandError> {
+    let seed = seed.unwrap_or_default();
+    let pick = |arg: &Option<String>, seed: Option<String>, name: &str| {
+        arg.clone()
+            .or(seed)
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| CommandError::usage(format!("缺少管理员{}", name)))
+    };
+
+    let data = setup::StepAccountData {
+        username: pick(&args.admin_username, seed.username, "用户名")?,
+        email: pick(&args.admin_email, seed.email, "邮箱")?,
+        password: pick(&args.admin_password, seed.password, "密码")?,
+    };
+    users::check_email(&data.email).map_err(CommandError::usage)?;
+    Ok(data)
+}
+
+// 与安装向导执行相同的步骤，数据库已安装时从创建管理员继续
+pub async fn run(args: &InstallArgs) -> CommandResult {
+    let config = config::Config::load().map_err(CommandError::usage)?;
+    if config.init.administrator {
+        return Err(CommandError::new(EXIT_INSTALLED, "站点已完成安装"));
+    }
+
+    let seed = match &args.seed {
+        Some(path) => Seed::read(path)?,
+        None => Seed::default(),
+    };
+    let administrator = resolve_administrator(args, seed.administrator)?;
+
+    let sql_config = if config.init.sql {
+        eprintln!("数据库已安装，跳过");
+        config.sql_config.clone()
+    } else {
+        let (sql_config, persisted) = resolve_sql_config(args, seed.sql_config)?;
+        setup::install_sql(&sql_config, persisted, args.overwrite).await?;
+        eprintln!("数据库安装完成");
+        sql_config
+    };
+
+    let sql = sql::Database::link(&sql_config).await?;
+    let result = async {
+        sql.migrate(false).await?;
+        setup::install_account(&sql, administrator, args.overwrite).await
+    }
+    .await;
+    sql.close().await?;
+    let response = result?;
+    eprintln!("管理员创建完成");
+
+    // 系统账户凭据只输出这一次，以 JSON 输出便于部署脚本读取
+    let output = serde_json::to_string_pretty(&response)
+        .map_err(|e| CommandError::new(EXIT_FAILURE, e))?;
+    println!("{}", output);
+    Ok(())
+}
@@ -0,0 +1,13 @@
+use super::CommandResult;
+use crate::common::cli::KeysCommand;
+use crate::security::jwt;
+
+pub fn run(command: &KeysCommand) -> CommandResult {
+    match command {
+        KeysCommand::Rotate => {
+            jwt::generate_key()?;
+            eprintln!("签名密钥已重新生成，之前签发的令牌全部失效");
+        }
+    }
+    Ok(())
+}
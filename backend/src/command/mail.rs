@@ -0,0 +1,18 @@
+use super::{CommandError, CommandResult};
+use crate::common::cli::MailCommand;
+use crate::common::config;
+use crate::mail::Mailer;
+
+pub async fn run(command: &MailCommand) -> CommandResult {
+    let config = config::Config::load().map_err(CommandError::usage)?;
+    match command {
+        MailCommand::Test { to } => {
+            let mailer = Mailer::new(&config.mail).map_err(CommandError::usage)?;
+            mailer
+                .send(to, "测试邮件", "这是一封测试邮件，收到说明邮件配置正确。\n")
+                .await?;
+            eprintln!("测试邮件已发送到 {}", to);
+        }
+    }
+    Ok(())
+}
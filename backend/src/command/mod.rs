@@ -0,0 +1,85 @@
+pub mod api_key;
+pub mod config;
+pub mod db;
+pub mod field;
+pub mod install;
+pub mod keys;
+pub mod mail;
+pub mod user;
+
+use crate::common::cli::Command;
+use crate::common::config as app_config;
+use crate::common::error::CustomError;
+use crate::storage::sql;
+use std::fmt::Display;
+
+// 退出码：0 成功，1 执行失败，2 参数或配置无效，3 站点已完成安装
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_INSTALLED: i32 = 3;
+
+#[derive(Debug)]
+pub struct CommandError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: i32, message: impl Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn usage(message: impl Display) -> Self {
+        Self::new(EXIT_USAGE, message)
+    }
+}
+
+impl From<CustomError> for CommandError {
+    fn from(error: CustomError) -> Self {
+        Self::new(EXIT_FAILURE, error)
+    }
+}
+
+pub type CommandResult = Result<(), CommandError>;
+
+// 连接配置中已安装的数据库，调用方用完后负责关闭
+async fn connect() -> Result<sql::Database, CommandError> {
+    let config = app_config::Config::load().map_err(CommandError::usage)?;
+    if !config.init.sql {
+        return Err(CommandError::usage("数据库尚未安装，请先执行 install"));
+    }
+    Ok(sql::Database::link(&config.sql_config).await?)
+}
+
+// 执行子命令并返回退出码，结果输出到标准输出，进度和错误输出到标准错误
+pub async fn run(command: &Command) -> i32 {
+    // 密码规则等按当前配置生效，与服务运行时一致；配置无效时由各子命令自行报错
+    if let Ok(config) = app_config::Config::load() {
+        app_config::Config::set_current(config);
+    }
+
+    let result = match command {
+        // serve 由 main 直接启动服务，不经过这里
+        Command::Serve => Err(CommandError::usage("serve 不是管理命令")),
+        Command::Install(args) => install::run(args).await,
+        Command::User(command) => user::run(command).await,
+        Command::Keys(command) => keys::run(command),
+        Command::ApiKey(command) => api_key::run(command).await,
+        Command::Db(command) => db::run(command).await,
+        Command::Field(command) => field::run(command).await,
+        Command::Config(command) => config::run(command),
+        Command::Mail(command) => mail::run(command).await,
+    };
+
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e.message);
+            e.code
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use super::{connect, CommandResult};
+use crate::api::auth::{mfa, throttle};
+use crate::api::users::{self, RegisterData, Role};
+use crate::common::cli::UserCommand;
+use crate::common::error::{CustomErrorInto, CustomResult};
+use crate::common::helpers;
+use crate::storage::sql;
+
+// 未指定密码时生成随机密码，第二项表示是否为生成的密码
+fn password_or_random(password: &Option<String>) -> (String, bool) {
+    match password {
+        Some(password) => (password.clone(), false),
+        None => (helpers::generate_random_string(16), true),
+    }
+}
+
+// 生成的密码只在操作成功后输出
+fn print_generated(password: &str, generated: bool) {
+    if generated {
+        println!("已生成密码: {}", password);
+    }
+}
+
+async fn execute(sql: &sql::Database, command: &UserCommand) -> CustomResult<()> {
+    match command {
+        UserCommand::Create {
+            username,
+            email,
+            password,
+            role,
+        } => {
+            let role = Role::from_str(role)?;
+            users::check_email(email)?;
+            let (password, generated) = password_or_random(password);
+            users::insert_user(
+                sql,
+                RegisterData {
+                    username: username.clone(),
+                    email: email.clone(),
+                    password: password.clone(),
+                    role,
+                },
+            )
+            .await?;
+            print_generated(&password, generated);
+            eprintln!("用户 {} 已创建", username);
+        }
+        UserCommand::ResetPassword { username, password } => {
+            // 用户不存在时 set_password 返回错误
+            let (password, generated) = password_or_random(password);
+            users::set_password(sql, username, &password).await?;
+            print_generated(&password, generated);
+            throttle::clear_user(sql, username).await?;
+            eprintln!("用户 {} 的密码已重置，登录锁定已解除", username);
+        }
+        UserCommand::ResetMfa { username } => {
+            if !users::user_exists(sql, username).await? {
+                return Err(format!("用户 {} 不存在", username).into_custom_error());
+            }
+            mfa::disable(sql, username).await?;
+            eprintln!("用户 {} 的两步验证已关闭", username);
+        }
+        UserCommand::List => {
+            let text = |row: &std::collections::HashMap<String, serde_json::Value>, key: &str| {
+                row.get(key)
+                    .and_then(|value| value.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            for row in users::list_users(sql).await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    text(&row, "username"),
+                    text(&row, "email"),
+                    text(&row, "role"),
+                    text(&row, "created_at")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(command: &UserCommand) -> CommandResult {
+    let sql = connect().await?;
+    let result = execute(&sql, command).await;
+    sql.close().await?;
+    Ok(result?)
+}
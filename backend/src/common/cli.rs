@@ -0,0 +1,212 @@
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+#[derive(Parser, Debug)]
+#[command(name = "echoes", version)]
+pub struct Cli {
+    /// 配置文件路径，默认为当前目录下的 config.toml
+    #[arg(long, env = "ECHOES_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// 覆盖配置中的监听地址
+    #[arg(long, global = true)]
+    pub address: Option<String>,
+
+    /// 覆盖配置中的监听端口
+    #[arg(long, global = true)]
+    pub port: Option<u32>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// 未指定子命令时等同于 serve
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 启动 HTTP 服务
+    Serve,
+    /// 不经过安装向导，直接安装数据库并创建管理员，完成后退出
+    Install(InstallArgs),
+    /// 用户管理
+    #[command(subcommand)]
+    User(UserCommand),
+    /// 签名密钥管理
+    #[command(subcommand)]
+    Keys(KeysCommand),
+    /// API 密钥管理
+    #[command(subcommand)]
+    ApiKey(ApiKeyCommand),
+    /// 数据库迁移
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// 字段读写
+    #[command(subcommand)]
+    Field(FieldCommand),
+    /// 配置查看
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// 邮件发送
+    #[command(subcommand)]
+    Mail(MailCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserCommand {
+    /// 创建用户，未指定密码时随机生成并输出
+    Create {
+        username: String,
+        email: String,
+        #[arg(long, env = "ECHOES_USER_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+        /// administrator/visitor
+        #[arg(long, default_value = "administrator")]
+        role: String,
+    },
+    /// 重置密码，未指定密码时随机生成并输出
+    ResetPassword {
+        username: String,
+        #[arg(long, env = "ECHOES_USER_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// 关闭用户的两步验证，用于丢失验证器和恢复码时找回账户
+    ResetMfa { username: String },
+    /// 列出所有用户
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeysCommand {
+    /// 重新生成签名密钥，已签发的令牌全部失效
+    Rotate,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ApiKeyCommand {
+    /// 创建 API 密钥，完整密钥只输出这一次
+    Create {
+        name: String,
+        /// 权限范围，可重复，如 --scope fields:read --scope fields:write
+        #[arg(long = "scope", required = true)]
+        scopes: Vec<String>,
+        /// 有效天数，不填表示永不过期
+        #[arg(long)]
+        expires_in_days: Option<i64>,
+    },
+    /// 列出所有 API 密钥
+    List,
+    /// 吊销 API 密钥
+    Revoke { id: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// 执行未应用的迁移
+    Migrate {
+        /// 只打印SQL，不执行
+        #[arg(long)]
+        dry_run: bool,
+        /// 回滚到指定的迁移版本
+        #[arg(long, value_name = "VERSION")]
+        rollback: Option<i64>,
+    },
+    /// 查看迁移状态和表结构偏差
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FieldCommand {
+    /// 读取目标的字段，指定 key 时只输出该字段
+    Get {
+        /// post/page/theme/system
+        target_type: String,
+        target_id: i64,
+        key: Option<String>,
+    },
+    /// 写入字段，已存在时更新
+    Set {
+        /// post/page/theme/system
+        target_type: String,
+        target_id: i64,
+        /// data/meta
+        field_type: String,
+        key: String,
+        value: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// 输出叠加环境变量和命令行参数后的生效配置
+    Show {
+        /// 同时输出密码
+        #[arg(long)]
+        show_secrets: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MailCommand {
+    /// 按当前配置发送一封测试邮件
+    Test { to: String },
+}
+
+#[derive(Args, Debug)]
+pub struct InstallArgs {
+    /// 安装参数文件，格式为包含 [sql_config] 和 [administrator] 的 TOML
+    #[arg(long, env = "ECHOES_SEED")]
+    pub seed: Option<PathBuf>,
+
+    /// 数据库类型: postgresql/mysql/sqllite
+    #[arg(long)]
+    pub db_type: Option<String>,
+
+    #[arg(long)]
+    pub db_host: Option<String>,
+
+    #[arg(long)]
+    pub db_port: Option<u32>,
+
+    #[arg(long)]
+    pub db_user: Option<String>,
+
+    /// 也可通过 ECHOES_SQL_CONFIG_PASSWORD 环境变量传入
+    #[arg(long)]
+    pub db_password: Option<String>,
+
+    #[arg(long)]
+    pub db_name: Option<String>,
+
+    #[arg(long)]
+    pub db_prefix: Option<String>,
+
+    #[arg(long, env = "ECHOES_ADMIN_USERNAME")]
+    pub admin_username: Option<String>,
+
+    #[arg(long, env = "ECHOES_ADMIN_EMAIL")]
+    pub admin_email: Option<String>,
+
+    #[arg(long, env = "ECHOES_ADMIN_PASSWORD", hide_env_values = true)]
+    pub admin_password: Option<String>,
+
+    /// 数据库中已有同前缀的表时删除后重建，已有同名管理员时覆盖其邮箱和密码
+    #[arg(long)]
+    pub overwrite: bool,
+}
+
+static CLI: OnceLock<Cli> = OnceLock::new();
+
+pub fn args() -> &'static Cli {
+    CLI.get_or_init(parse)
+}
+
+#[cfg(not(test))]
+fn parse() -> Cli {
+    Cli::parse()
+}
+
+// 测试二进制的参数属于测试框架，这里按无参数启动处理
+#[cfg(test)]
+fn parse() -> Cli {
+    Cli::parse_from([env!("CARGO_PKG_NAME")])
+}
@@ -1,14 +1,32 @@
-use crate::common::error::CustomResult;
+use crate::common::cli;
+use crate::common::error::{CustomError, CustomResult, ErrorKind};
+use crate::common::i18n::Locale;
+use percent_encoding::percent_decode_str;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 use std::{env, fs};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub address: String,
     pub port: u32,
+    // 接口返回信息的默认语言（zh-CN 或 en），请求的 Accept-Language 优先
+    pub locale: String,
     pub init: Init,
     pub sql_config: SqlConfig,
+    pub shutdown: ShutdownConfig,
+    pub login_limit: LoginLimitConfig,
+    pub mail: MailConfig,
+    pub password: PasswordConfig,
+    // 外部身份提供方，键为提供方名称，如 [oidc.google]
+    pub oidc: BTreeMap<String, OidcProviderConfig>,
+    // 开发模式，启用后挂载调试接口，生产环境必须关闭
+    pub dev_mode: bool,
 }
 
 impl Default for Config {
@@ -16,13 +34,298 @@ impl Default for Config {
         Self {
             address: "0.0.0.0".to_string(),
             port: 22000,
+            locale: "zh-CN".to_string(),
             init: Init::default(),
             sql_config: SqlConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            login_limit: LoginLimitConfig::default(),
+            mail: MailConfig::default(),
+            password: PasswordConfig::default(),
+            oidc: BTreeMap::new(),
+            dev_mode: false,
         }
     }
 }
 
+// 收到 SIGTERM/SIGINT 后停止接收新请求，单位为秒
 #[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    // 等待进行中的请求和后台任务完成的时间
+    pub grace: u32,
+    // 宽限期结束后强制关闭连接前的额外等待时间
+    pub mercy: u32,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace: 10,
+            mercy: 5,
+        }
+    }
+}
+
+// 登录失败限制，按 IP 和用户名分别计数，时间单位均为秒
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginLimitConfig {
+    // 第 n 次失败后需等待 base_delay * 2^(n-1) 秒才能再次尝试，最长 max_delay
+    pub base_delay: u64,
+    pub max_delay: u64,
+    // 连续失败达到该次数后锁定 lockout 秒
+    pub max_failures: u32,
+    pub lockout: u64,
+    // 超过该时间没有失败记录时重新计数
+    pub window: u64,
+}
+
+impl Default for LoginLimitConfig {
+    fn default() -> Self {
+        Self {
+            base_delay: 1,
+            max_delay: 60,
+            max_failures: 10,
+            lockout: 900,
+            window: 3600,
+        }
+    }
+}
+
+// 密码规则和哈希参数，修改哈希参数后旧密码在下次登录时按新参数重新计算
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordConfig {
+    // 按字符计算
+    pub min_length: u32,
+    // 禁用密码列表文件，每行一个，不区分大小写，为空表示不启用
+    pub blocklist: String,
+    // bcrypt/argon2id
+    pub algorithm: String,
+    // 4-31
+    pub bcrypt_cost: u32,
+    pub argon2: Argon2Config,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            blocklist: "".to_string(),
+            algorithm: "bcrypt".to_string(),
+            bcrypt_cost: 12,
+            argon2: Argon2Config::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Argon2Config {
+    // 单位为 KiB
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Self {
+            memory: 19456,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordConfig {
+    pub fn validate(&self) -> CustomResult<()> {
+        if self.min_length == 0 {
+            return Err(positive("password.min_length"));
+        }
+        if !self.blocklist.is_empty() && !PathBuf::from(&self.blocklist).is_file() {
+            return Err(
+                CustomError::localized(ErrorKind::Internal, "config.blocklist_missing")
+                    .with_arg("path", &self.blocklist),
+            );
+        }
+        match self.algorithm.to_lowercase().as_str() {
+            "bcrypt" => {
+                if !(4..=31).contains(&self.bcrypt_cost) {
+                    return Err(out_of_range(
+                        "password.bcrypt_cost",
+                        "4-31",
+                        self.bcrypt_cost,
+                    ));
+                }
+            }
+            "argon2id" => {
+                argon2::Params::new(
+                    self.argon2.memory,
+                    self.argon2.iterations,
+                    self.argon2.parallelism,
+                    None,
+                )
+                .map_err(|e| invalid("password.argon2", e))?;
+            }
+            algorithm => return Err(unknown("password.algorithm", algorithm, "bcrypt/argon2id")),
+        }
+        Ok(())
+    }
+}
+
+// OpenID Connect 提供方，使用授权码流程和 PKCE
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcProviderConfig {
+    // 通过 <issuer>/.well-known/openid-configuration 获取端点
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    // 在提供方登记的回调地址，通常是前端页面，由前端把 code 和 state 提交给后端
+    pub redirect_url: String,
+    // 空格分隔
+    pub scopes: String,
+    // 提供方确认邮箱已验证且与本地用户邮箱一致时自动关联
+    pub link_by_email: bool,
+}
+
+impl Default for OidcProviderConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "".to_string(),
+            client_id: "".to_string(),
+            client_secret: "".to_string(),
+            redirect_url: "".to_string(),
+            scopes: "openid email profile".to_string(),
+            link_by_email: false,
+        }
+    }
+}
+
+impl OidcProviderConfig {
+    pub fn validate(&self, name: &str) -> CustomResult<()> {
+        if !regex::Regex::new(r"^[a-z0-9_]{1,50}$")?.is_match(name) {
+            return Err(CustomError::localized(
+                ErrorKind::Internal,
+                "config.invalid_provider_name",
+            )
+            .with_arg("name", name));
+        }
+        url::Url::parse(&self.issuer)
+            .map_err(|_| invalid(&format!("oidc.{}.issuer", name), &self.issuer))?;
+        url::Url::parse(&self.redirect_url)
+            .map_err(|_| invalid(&format!("oidc.{}.redirect_url", name), &self.redirect_url))?;
+        if self.client_id.is_empty() {
+            return Err(required(&format!("oidc.{}.client_id", name)));
+        }
+        if !self.scopes.split_whitespace().any(|scope| scope == "openid") {
+            return Err(
+                CustomError::localized(ErrorKind::Internal, "config.scopes_need_openid")
+                    .with_arg("name", name),
+            );
+        }
+        Ok(())
+    }
+}
+
+// 外发邮件，用于找回密码和验证邮箱
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    // none/smtp/capture，capture 将邮件以 JSON 写入 capture_dir 而不发送，用于开发和测试
+    pub backend: String,
+    // 发件人，如 "Echoes <noreply@example.com>"
+    pub from: String,
+    // 站点地址，用于拼接邮件中的链接，为空时邮件中只包含令牌
+    pub site_url: String,
+    // 为空时使用 assets/mail
+    pub capture_dir: String,
+    pub smtp: SmtpConfig,
+    // 以下单位为秒
+    pub reset_ttl: u64,
+    pub verify_ttl: u64,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            backend: "none".to_string(),
+            from: "".to_string(),
+            site_url: "".to_string(),
+            capture_dir: "".to_string(),
+            smtp: SmtpConfig::default(),
+            reset_ttl: 3600,
+            verify_ttl: 86400,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u32,
+    pub username: String,
+    pub password: String,
+    // starttls/tls/none
+    pub security: String,
+    // 单位为秒
+    pub timeout: u64,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            host: "".to_string(),
+            port: 587,
+            username: "".to_string(),
+            password: "".to_string(),
+            security: "starttls".to_string(),
+            timeout: 10,
+        }
+    }
+}
+
+impl MailConfig {
+    pub fn validate(&self) -> CustomResult<()> {
+        match self.backend.to_lowercase().as_str() {
+            "none" => return Ok(()),
+            "capture" => {}
+            "smtp" => {
+                if self.smtp.host.is_empty() {
+                    return Err(required("mail.smtp.host"));
+                }
+                check_port("mail.smtp.port", self.smtp.port)?;
+                if !matches!(
+                    self.smtp.security.to_lowercase().as_str(),
+                    "starttls" | "tls" | "none"
+                ) {
+                    return Err(unknown(
+                        "mail.smtp.security",
+                        &self.smtp.security,
+                        "starttls/tls/none",
+                    ));
+                }
+            }
+            backend => return Err(unknown("mail.backend", backend, "none/smtp/capture")),
+        }
+        // 启用邮件发送时才需要发件人
+        if self.from.is_empty() {
+            return Err(required("mail.from"));
+        }
+        if self.reset_ttl == 0 {
+            return Err(positive("mail.reset_ttl"));
+        }
+        if self.verify_ttl == 0 {
+            return Err(positive("mail.verify_ttl"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct Init {
     pub sql: bool,
     pub no_sql: bool,
@@ -40,6 +343,7 @@ impl Default for Init {
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct SqlConfig {
     pub db_type: String,
     pub host: String,
@@ -48,6 +352,152 @@ pub struct SqlConfig {
     pub password: String,
     pub db_name: String,
     pub db_prefix: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    // 以下时间单位均为秒，idle_timeout 和 max_lifetime 为 0 表示不限制
+    pub acquire_timeout: u64,
+    pub idle_timeout: u64,
+    pub max_lifetime: u64,
+    // 为空时使用驱动默认值，PostgreSQL: disable/prefer/require/verify-ca/verify-full，MySQL: disabled/preferred/required/verify_ca/verify_identity
+    pub ssl_mode: String,
+    // 原样附加到连接串的额外参数，如 sslrootcert、charset、application_name；SQLite 下作为 PRAGMA 设置
+    pub options: BTreeMap<String, String>,
+    pub sqlite: SqliteConfig,
+    // 只读副本，仅支持 PostgreSQL 和 MySQL，其余连接参数与主库相同
+    pub replicas: Vec<ReplicaConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqliteConfig {
+    // 数据库文件的绝对路径，为空时使用 assets/sqllite/<db_name>，":memory:" 表示内存数据库
+    pub path: String,
+    // delete/truncate/persist/memory/wal/off
+    pub journal_mode: String,
+    // 单位为秒
+    pub busy_timeout: u64,
+    pub foreign_keys: bool,
+    // off/normal/full/extra
+    pub synchronous: String,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            path: "".to_string(),
+            journal_mode: "wal".to_string(),
+            busy_timeout: 5,
+            foreign_keys: true,
+            synchronous: "normal".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplicaConfig {
+    pub host: String,
+    pub port: u32,
+}
+
+impl SqlConfig {
+    pub fn validate(&self) -> CustomResult<()> {
+        match self.db_type.to_lowercase().as_str() {
+            "postgresql" | "mysql" => {
+                check_port("sql_config.port", self.port)?;
+                for replica in &self.replicas {
+                    check_port("sql_config.replicas.port", replica.port)?;
+                }
+            }
+            "sqllite" => {
+                if !self.replicas.is_empty() {
+                    return Err(CustomError::localized(
+                        ErrorKind::Internal,
+                        "config.sqlite_replicas",
+                    ));
+                }
+            }
+            db_type => {
+                return Err(unknown(
+                    "sql_config.db_type",
+                    db_type,
+                    "postgresql/mysql/sqllite",
+                ))
+            }
+        }
+        if self.db_name.is_empty() {
+            return Err(required("sql_config.db_name"));
+        }
+        if self.max_connections == 0 || self.min_connections > self.max_connections {
+            return Err(CustomError::localized(
+                ErrorKind::Internal,
+                "config.connection_limits",
+            ));
+        }
+        // 获取连接超时为 0 时每次获取都会立即失败，idle_timeout 和 max_lifetime 的 0 表示不限制
+        if self.acquire_timeout == 0 {
+            return Err(positive("sql_config.acquire_timeout"));
+        }
+        if !regex::Regex::new(r"^[a-zA-Z][a-zA-Z0-9_]{0,31}$")?.is_match(&self.db_prefix) {
+            return Err(invalid("sql_config.db_prefix", &self.db_prefix));
+        }
+        Ok(())
+    }
+
+    // 只叠加 DATABASE_URL 和 ECHOES_SQL_CONFIG_* 环境变量，用于不来自配置文件的数据库配置
+    pub fn apply_env(&mut self) -> CustomResult<()> {
+        if let Ok(database_url) = env::var("DATABASE_URL") {
+            self.apply_url(&database_url)?;
+        }
+        let mut value = toml::Value::try_from(&*self)?;
+        apply_env(&mut value, &format!("{}_SQL_CONFIG", ENV_PREFIX))?;
+        *self = value.try_into()?;
+        Ok(())
+    }
+
+    // 拆解 DATABASE_URL，用户名和密码按百分号编码解码
+    pub fn apply_url(&mut self, database_url: &str) -> CustomResult<()> {
+        if let Some(path) = database_url.strip_prefix("sqlite:") {
+            self.db_type = "sqllite".to_string();
+            self.sqlite.path = path.trim_start_matches("//").to_string();
+            return Ok(());
+        }
+
+        let url = url::Url::parse(database_url)?;
+        self.db_type = match url.scheme() {
+            "postgres" | "postgresql" => "postgresql",
+            "mysql" | "mariadb" => "mysql",
+            scheme => {
+                return Err(
+                    CustomError::localized(ErrorKind::Internal, "config.unsupported_url")
+                        .with_arg("scheme", scheme),
+                )
+            }
+        }
+        .to_string();
+        self.host = url.host_str().unwrap_or_default().to_string();
+        if let Some(port) = url.port() {
+            self.port = port.into();
+        }
+        let decode = |text: &str| -> CustomResult<String> {
+            Ok(percent_decode_str(text).decode_utf8()?.into_owned())
+        };
+        self.user = decode(url.username())?;
+        self.password = decode(url.password().unwrap_or_default())?;
+        let db_name = decode(url.path().trim_start_matches('/'))?;
+        if !db_name.is_empty() {
+            self.db_name = db_name;
+        }
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "sslmode" | "ssl-mode" => self.ssl_mode = value.into_owned(),
+                _ => {
+                    self.options.insert(key.into_owned(), value.into_owned());
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Default for SqlConfig {
@@ -60,6 +510,15 @@ impl Default for SqlConfig {
             password: "".to_string(),
             db_name: "echoes".to_string(),
             db_prefix: "echoes_".to_string(),
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: 5,
+            idle_timeout: 600,
+            max_lifetime: 1800,
+            ssl_mode: "".to_string(),
+            options: BTreeMap::new(),
+            sqlite: SqliteConfig::default(),
+            replicas: Vec::new(),
         }
     }
 }
@@ -87,7 +546,169 @@ impl Default for NoSqlConfig {
     }
 }
 
+const ENV_PREFIX: &str = "ECHOES";
+
+// 按字段路径查找环境变量，如 ECHOES_PORT、ECHOES_SQL_CONFIG_PASSWORD、ECHOES_SQL_CONFIG_SQLITE_PATH
+fn apply_env(value: &mut toml::Value, name: &str) -> CustomResult<()> {
+    if let toml::Value::Table(table) = value {
+        for (key, child) in table.iter_mut() {
+            apply_env(child, &format!("{}_{}", name, key.to_uppercase()))?;
+        }
+        return Ok(());
+    }
+
+    let Ok(raw) = env::var(name) else {
+        return Ok(());
+    };
+    *value = match value {
+        toml::Value::Integer(_) => toml::Value::Integer(
+            raw.parse()
+                .map_err(|_| env_error("config.env_integer", name))?,
+        ),
+        toml::Value::Boolean(_) => toml::Value::Boolean(
+            raw.parse()
+                .map_err(|_| env_error("config.env_boolean", name))?,
+        ),
+        toml::Value::Array(_) => return Err(env_error("config.env_list", name)),
+        _ => toml::Value::String(raw),
+    };
+    Ok(())
+}
+
+fn check_port(name: &str, port: u32) -> CustomResult<()> {
+    if port == 0 || port > u16::MAX as u32 {
+        return Err(out_of_range(name, "1-65535", port));
+    }
+    Ok(())
+}
+
+// 配置校验错误按语言包输出，参数中保留原始字段名
+fn required(field: &str) -> CustomError {
+    CustomError::localized(ErrorKind::Internal, "config.required").with_arg("field", field)
+}
+
+fn positive(field: &str) -> CustomError {
+    CustomError::localized(ErrorKind::Internal, "config.must_be_positive").with_arg("field", field)
+}
+
+fn invalid(field: &str, value: impl ToString) -> CustomError {
+    CustomError::localized(ErrorKind::Internal, "config.invalid_value")
+        .with_arg("field", field)
+        .with_arg("value", value)
+}
+
+fn unknown(field: &str, value: &str, options: &str) -> CustomError {
+    CustomError::localized(ErrorKind::Internal, "config.unknown_option")
+        .with_arg("field", field)
+        .with_arg("value", value)
+        .with_arg("options", options)
+}
+
+fn out_of_range(field: &str, range: &str, value: impl ToString) -> CustomError {
+    CustomError::localized(ErrorKind::Internal, "config.out_of_range")
+        .with_arg("field", field)
+        .with_arg("range", range)
+        .with_arg("value", value)
+}
+
+fn env_error(key: &'static str, name: &str) -> CustomError {
+    CustomError::localized(ErrorKind::Internal, key).with_arg("name", name)
+}
+
+static CURRENT: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+
 impl Config {
+    // 按 默认值 < 配置文件 < DATABASE_URL < ECHOES_* 环境变量 < 命令行参数 的顺序叠加，结果不会写回文件
+    pub fn load() -> CustomResult<Self> {
+        let path = Self::get_path()?;
+        let mut config = if path.exists() {
+            Self::read().map_err(|e| {
+                CustomError::localized(ErrorKind::Internal, "config.parse_failed")
+                    .with_arg("path", path.display())
+                    .with_arg("error", e)
+            })?
+        } else {
+            Self::default()
+        };
+
+        if let Ok(database_url) = env::var("DATABASE_URL") {
+            config.sql_config.apply_url(&database_url)?;
+        }
+
+        let mut value = toml::Value::try_from(&config)?;
+        apply_env(&mut value, ENV_PREFIX)?;
+        let mut config: Self = value.try_into()?;
+
+        let args = cli::args();
+        if let Some(address) = &args.address {
+            config.address = address.clone();
+        }
+        if let Some(port) = args.port {
+            config.port = port;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> CustomResult<()> {
+        self.address
+            .parse::<IpAddr>()
+            .map_err(|_| invalid("address", &self.address))?;
+        check_port("port", self.port)?;
+        if Locale::parse(&self.locale).is_none() {
+            return Err(unknown("locale", &self.locale, "zh-CN/en"));
+        }
+        if self.login_limit.max_failures == 0 {
+            return Err(positive("login_limit.max_failures"));
+        }
+        self.mail.validate()?;
+        self.password.validate()?;
+        for (name, provider) in &self.oidc {
+            provider.validate(name)?;
+        }
+
+        self.sql_config.validate()
+    }
+
+    // 运行中的配置，热重载后会被替换
+    pub fn current() -> Arc<Self> {
+        CURRENT
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .unwrap_or_default()
+    }
+
+    pub fn set_current(config: Config) {
+        *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(config));
+    }
+
+    // 监听地址、端口、关闭参数和开发模式在启动时交给 Rocket，修改后需要重启才能生效，这里沿用运行中的值；
+    // 数据库和初始化状态由 AppState::reconfigure 在进程内切换
+    pub fn reload() -> CustomResult<Self> {
+        let running = Self::current();
+        let mut config = Self::load()?;
+
+        if config.address != running.address
+            || config.port != running.port
+            || toml::to_string(&config.shutdown)? != toml::to_string(&running.shutdown)?
+            || config.dev_mode != running.dev_mode
+        {
+            eprintln!("监听地址、端口、关闭参数或开发模式的修改需要重启后生效");
+        }
+        config.address = running.address.clone();
+        config.port = running.port;
+        config.shutdown = running.shutdown.clone();
+        config.dev_mode = running.dev_mode;
+        Ok(config)
+    }
+
+    // 配置文件的修改时间，用于轮询热重载
+    pub fn modified() -> Option<SystemTime> {
+        fs::metadata(Self::get_path().ok()?).ok()?.modified().ok()
+    }
+
     pub fn read() -> CustomResult<Self> {
         let path = Self::get_path()?;
         Ok(toml::from_str(&fs::read_to_string(path)?)?)
@@ -99,6 +720,9 @@ impl Config {
     }
 
     pub fn get_path() -> CustomResult<PathBuf> {
-        Ok(env::current_dir()?.join("config.toml"))
+        match &cli::args().config {
+            Some(path) => Ok(path.clone()),
+            None => Ok(env::current_dir()?.join("config.toml")),
+        }
     }
 }
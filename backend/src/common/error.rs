@@ -1,41 +1,206 @@
+use crate::common::i18n::Locale;
 use rocket::http::Status;
-use rocket::response::status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use serde::Serialize;
+use std::any::Any;
 
-pub type AppResult<T> = Result<T, status::Custom<String>>;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Validation,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    TooManyRequests,
+    Unavailable,
+    // 外部服务（如身份提供方）返回错误
+    Upstream,
+    Database,
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn status(&self) -> Status {
+        match self {
+            ErrorKind::Validation => Status::BadRequest,
+            ErrorKind::Unauthorized => Status::Unauthorized,
+            ErrorKind::Forbidden => Status::Forbidden,
+            ErrorKind::NotFound => Status::NotFound,
+            ErrorKind::Conflict => Status::Conflict,
+            ErrorKind::TooManyRequests => Status::TooManyRequests,
+            ErrorKind::Unavailable => Status::ServiceUnavailable,
+            ErrorKind::Upstream => Status::BadGateway,
+            ErrorKind::Database | ErrorKind::Internal => Status::InternalServerError,
+        }
+    }
+
+    pub fn from_status(status: Status) -> Self {
+        match status.code {
+            401 => ErrorKind::Unauthorized,
+            403 => ErrorKind::Forbidden,
+            404 => ErrorKind::NotFound,
+            409 => ErrorKind::Conflict,
+            429 => ErrorKind::TooManyRequests,
+            502 => ErrorKind::Upstream,
+            503 => ErrorKind::Unavailable,
+            400..=499 => ErrorKind::Validation,
+            _ => ErrorKind::Internal,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::Validation => "validation",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::Forbidden => "forbidden",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::TooManyRequests => "too_many_requests",
+            ErrorKind::Unavailable => "unavailable",
+            ErrorKind::Upstream => "upstream",
+            ErrorKind::Database => "database",
+            ErrorKind::Internal => "internal",
+        }
+    }
+
+    pub fn default_message(&self, locale: Locale) -> String {
+        locale.text(&format!("error.{}", self.code()))
+    }
 
-pub trait AppResultInto<T> {
-    fn into_app_result(self) -> AppResult<T>;
+    // 这些错误的详情可能包含SQL、文件路径或内部地址，只记录日志，不返回给客户端
+    fn is_internal(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Database | ErrorKind::Internal | ErrorKind::Upstream
+        )
+    }
 }
 
+// 带 key 的错误按请求语言从语言包取文本，其余错误原样输出 message
 #[derive(Debug)]
-pub struct CustomError(String);
+pub struct CustomError {
+    kind: ErrorKind,
+    message: String,
+    key: Option<&'static str>,
+    args: Vec<(&'static str, String)>,
+}
+
+impl CustomError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            key: None,
+            args: Vec::new(),
+        }
+    }
+
+    pub fn localized(kind: ErrorKind, key: &'static str) -> Self {
+        Self {
+            kind,
+            message: String::new(),
+            key: Some(key),
+            args: Vec::new(),
+        }
+    }
+
+    pub fn with_arg(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+
+    pub fn message(&self, locale: Locale) -> String {
+        match self.key {
+            Some(key) => locale.format(key, &self.args),
+            None => self.message.clone(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    // 保留原始信息，修改错误类型
+    pub fn with_kind(self, kind: ErrorKind) -> Self {
+        Self { kind, ..self }
+    }
+}
 
 impl std::fmt::Display for CustomError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.message(Locale::site()))
     }
 }
 
 pub trait CustomErrorInto {
     fn into_custom_error(self) -> CustomError;
+    fn into_error(self, kind: ErrorKind) -> CustomError;
 }
 
+// 未指定类型的错误按内部错误处理，需要展示给用户的信息应使用 into_error 指定类型
 impl CustomErrorInto for &str {
     fn into_custom_error(self) -> CustomError {
-        CustomError(self.to_string())
+        self.into_error(ErrorKind::Internal)
+    }
+
+    fn into_error(self, kind: ErrorKind) -> CustomError {
+        CustomError::new(kind, self)
     }
 }
 
-impl<E: std::error::Error> From<E> for CustomError {
+// 违反唯一约束（含主键）时返回冲突，数据库原始信息可能包含表结构，不返回给客户端
+impl<E: std::error::Error + 'static> From<E> for CustomError {
     fn from(error: E) -> Self {
-        CustomError(error.to_string())
+        let kind = match (&error as &dyn Any).downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::Database(db_error)) if db_error.is_unique_violation() => {
+                return CustomError::localized(ErrorKind::Conflict, "error.conflict");
+            }
+            Some(_) => ErrorKind::Database,
+            None => ErrorKind::Internal,
+        };
+        CustomError::new(kind, error.to_string())
     }
 }
 
 pub type CustomResult<T> = Result<T, CustomError>;
 
-impl<T> AppResultInto<T> for CustomResult<T> {
-    fn into_app_result(self) -> AppResult<T> {
-        self.map_err(|e| status::Custom(Status::InternalServerError, e.to_string()))
+// 接口处理函数的返回类型，错误以 JSON 返回
+pub type AppResult<T> = CustomResult<T>;
+
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ErrorBody {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            code: kind.code(),
+            message: message.into(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for CustomError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let locale = Locale::of(request);
+        let message = if self.kind.is_internal() {
+            eprintln!(
+                "{} {} 失败 [{}]: {}",
+                request.method(),
+                request.uri(),
+                self.kind.code(),
+                self
+            );
+            self.kind.default_message(locale)
+        } else {
+            self.message(locale)
+        };
+        let mut response = Json(ErrorBody::new(self.kind, message)).respond_to(request)?;
+        response.set_status(self.kind.status());
+        Ok(response)
     }
 }
@@ -7,3 +7,8 @@ pub fn generate_random_string(length: usize) -> String {
         .map(|_| *charset.choose(&mut rng).unwrap() as char)
         .collect()
 }
+
+// 逐字节比较全部内容，避免耗时泄露匹配长度
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
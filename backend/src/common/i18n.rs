@@ -0,0 +1,121 @@
+use crate::common::config;
+use rocket::request::{FromRequest, Outcome, Request};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Locale {
+    ZhCn,
+    En,
+}
+
+static ZH_CN: OnceLock<HashMap<String, String>> = OnceLock::new();
+static EN: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+// 语言包按 [分组] 键 = "文本" 编写，展开为 分组.键
+fn flatten(prefix: &str, table: &toml::Table, messages: &mut HashMap<String, String>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match value {
+            toml::Value::String(text) => {
+                messages.insert(key, text.clone());
+            }
+            toml::Value::Table(table) => flatten(&key, table, messages),
+            _ => {}
+        }
+    }
+}
+
+fn catalog(locale: Locale) -> &'static HashMap<String, String> {
+    let (cell, source) = match locale {
+        Locale::ZhCn => (&ZH_CN, include_str!("locales/zh-CN.toml")),
+        Locale::En => (&EN, include_str!("locales/en.toml")),
+    };
+    cell.get_or_init(|| {
+        let mut messages = HashMap::new();
+        match source.parse::<toml::Table>() {
+            Ok(table) => flatten("", &table, &mut messages),
+            Err(e) => eprintln!("语言包 {} 解析失败: {}", locale.code(), e),
+        }
+        messages
+    })
+}
+
+impl Locale {
+    // 只比较主语言，zh-TW、en-GB 等按 zh-CN、en 处理
+    pub fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.trim().to_lowercase();
+        match tag.split(['-', '_']).next().unwrap_or_default() {
+            "zh" => Some(Locale::ZhCn),
+            "en" => Some(Locale::En),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Locale::ZhCn => "zh-CN",
+            Locale::En => "en",
+        }
+    }
+
+    // 按 q 值从高到低选择第一个支持的语言
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        let mut tags: Vec<(f32, &str)> = header
+            .split(',')
+            .filter_map(|part| {
+                let mut pieces = part.split(';');
+                let tag = pieces.next()?.trim();
+                let quality = pieces
+                    .find_map(|piece| piece.trim().strip_prefix("q="))
+                    .map(|q| q.parse().unwrap_or(0.0))
+                    .unwrap_or(1.0);
+                Some((quality, tag))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        tags.sort_by(|a, b| b.0.total_cmp(&a.0));
+        tags.into_iter().find_map(|(_, tag)| Self::parse(tag))
+    }
+
+    // 站点默认语言，命令行输出和请求未指定语言时使用
+    pub fn site() -> Self {
+        Self::parse(&config::Config::current().locale).unwrap_or(Locale::ZhCn)
+    }
+
+    pub fn of(request: &Request<'_>) -> Self {
+        request
+            .headers()
+            .get_one("Accept-Language")
+            .and_then(Self::from_accept_language)
+            .unwrap_or_else(Self::site)
+    }
+
+    pub fn text(&self, key: &str) -> String {
+        self.format(key, &[])
+    }
+
+    // 缺少翻译时依次回退到中文和键名，参数以 {名称} 写在文本中
+    pub fn format(&self, key: &str, args: &[(&str, String)]) -> String {
+        let template = catalog(*self)
+            .get(key)
+            .or_else(|| catalog(Locale::ZhCn).get(key))
+            .map(String::as_str)
+            .unwrap_or(key);
+        args.iter().fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Locale {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Locale::of(request))
+    }
+}
@@ -1,3 +1,5 @@
+pub mod cli;
 pub mod config;
 pub mod error;
 pub mod helpers;
+pub mod i18n;
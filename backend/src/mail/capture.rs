@@ -0,0 +1,80 @@
+use super::{Mail, MailTransport};
+use crate::common::error::CustomResult;
+use crate::common::helpers;
+use async_trait::async_trait;
+use chrono::Utc;
+use std::{env, path::PathBuf};
+
+// 不发送邮件，每封邮件写入一个 JSON 文件，文件名按时间排序
+pub struct Capture {
+    dir: PathBuf,
+}
+
+impl Capture {
+    pub fn new(dir: &str) -> CustomResult<Self> {
+        let dir = match dir {
+            "" => env::current_dir()?.join("assets").join("mail"),
+            dir => PathBuf::from(dir),
+        };
+        Ok(Self { dir })
+    }
+}
+
+#[async_trait]
+impl MailTransport for Capture {
+    async fn send(&self, mail: &Mail) -> CustomResult<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let name = format!(
+            "{}-{}.json",
+            Utc::now().format("%Y%m%d%H%M%S%f"),
+            helpers::generate_random_string(6)
+        );
+        tokio::fs::write(self.dir.join(name), serde_json::to_string_pretty(mail)?).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn writes_each_mail_as_json() {
+        let dir = env::temp_dir().join(format!(
+            "echoes-capture-{}",
+            helpers::generate_random_string(8)
+        ));
+        let capture = Capture::new(&dir.display().to_string()).unwrap();
+        for subject in ["first", "second"] {
+            capture
+                .send(&Mail {
+                    from: "noreply@example.com".to_string(),
+                    to: "alice@example.com".to_string(),
+                    subject: subject.to_string(),
+                    body: "hello".to_string(),
+                })
+                .await
+                .unwrap();
+        }
+
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        let mails = files
+            .iter()
+            .map(|path| {
+                serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(path).unwrap())
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(mails.len(), 2);
+        assert_eq!(mails[0]["subject"], "first");
+        assert_eq!(mails[1]["subject"], "second");
+        assert_eq!(mails[1]["to"], "alice@example.com");
+        assert_eq!(mails[1]["from"], "noreply@example.com");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+mod capture;
+mod smtp;
+
+use crate::common::config::MailConfig;
+use crate::common::error::{CustomError, CustomResult, ErrorKind};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mail {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, mail: &Mail) -> CustomResult<()>;
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+    from: String,
+    transport: Arc<dyn MailTransport>,
+}
+
+impl Mailer {
+    // 按配置选择发送方式，backend 为 none 时返回错误
+    pub fn new(config: &MailConfig) -> CustomResult<Self> {
+        let transport: Arc<dyn MailTransport> = match config.backend.to_lowercase().as_str() {
+            "smtp" => Arc::new(smtp::Smtp::new(&config.smtp)?),
+            "capture" => Arc::new(capture::Capture::new(&config.capture_dir)?),
+            _ => return Err(CustomError::localized(ErrorKind::Unavailable, "mail.not_configured")),
+        };
+        Ok(Self {
+            from: config.from.clone(),
+            transport,
+        })
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: &str) -> CustomResult<()> {
+        self.transport
+            .send(&Mail {
+                from: self.from.clone(),
+                to: to.to_string(),
+                subject: subject.to_string(),
+                body: body.to_string(),
+            })
+            .await
+    }
+}
@@ -1,66 +1,120 @@
 mod api;
+mod command;
 mod common;
+mod mail;
 mod security;
 mod storage;
 
 use crate::common::config;
-use common::error::{CustomErrorInto, CustomResult};
+use common::error::{CustomError, CustomResult, ErrorKind};
 use rocket::http::Method;
-use rocket::{Shutdown, routes};
 use rocket_cors::{AllowedHeaders, AllowedOrigins, Cors, CorsOptions};
+use rocket::fairing::AdHoc;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use storage::sql;
 use tokio::sync::Mutex;
+use tokio::task::JoinSet;
 
 pub struct AppState {
     db: Arc<Mutex<Option<sql::Database>>>,
-    shutdown: Arc<Mutex<Option<Shutdown>>>,
-    restart_progress: Arc<Mutex<bool>>,
+    jobs: Arc<Mutex<JoinSet<()>>>,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
             db: Arc::new(Mutex::new(None)),
-            shutdown: Arc::new(Mutex::new(None)),
-            restart_progress: Arc::new(Mutex::new(false)),
+            jobs: Arc::new(Mutex::new(JoinSet::new())),
         }
     }
 
+    // 后台任务，关闭时会在宽限期内等待其完成
+    pub async fn spawn_job<F>(&self, job: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut jobs = self.jobs.lock().await;
+        while jobs.try_join_next().is_some() {}
+        jobs.spawn(job);
+    }
+
+    // 等待后台任务完成，超时则中止，随后关闭数据库连接池
+    pub async fn shutdown(&self, grace: Duration) -> CustomResult<()> {
+        let mut jobs = std::mem::take(&mut *self.jobs.lock().await);
+        let pending = jobs.len();
+        let flushed = tokio::time::timeout(grace, async {
+            while jobs.join_next().await.is_some() {}
+        })
+        .await
+        .is_ok();
+        if flushed {
+            println!("后台任务已完成: {}", pending);
+        } else {
+            eprintln!("后台任务未在宽限期内完成，已中止: {}", jobs.len());
+            jobs.abort_all();
+        }
+
+        if let Some(db) = self.db.lock().await.take() {
+            db.close().await?;
+            println!("数据库连接池已关闭");
+        }
+        Ok(())
+    }
+
+    // 每次调用得到一个新的数据库会话，请求处理中获取一次并复用，写入后的读取才会走主库
     pub async fn sql_get(&self) -> CustomResult<sql::Database> {
         self.db
             .lock()
             .await
-            .clone()
-            .ok_or_else(|| "数据库未连接".into_custom_error())
+            .as_ref()
+            .map(sql::Database::session)
+            .ok_or_else(|| CustomError::localized(ErrorKind::Unavailable, "database.not_connected"))
     }
 
+    // 新连接可用后再替换，旧连接池在正在执行的请求结束后关闭
     pub async fn sql_link(&self, config: &config::SqlConfig) -> CustomResult<()> {
-        match sql::Database::link(config).await {
-            Ok(db) => {
-                *self.db.lock().await = Some(db);
-                Ok(())
-            }
-            Err(e) => Err(e),
+        let db = sql::Database::link(config).await?;
+        let previous = self.db.lock().await.replace(db);
+        if let Some(previous) = previous {
+            previous.close().await?;
         }
+        Ok(())
     }
 
-    pub async fn set_shutdown(&self, shutdown: Shutdown) {
-        *self.shutdown.lock().await = Some(shutdown);
+    // 安装步骤写入配置或配置文件修改后在进程内切换到新配置，由阶段守卫切换可用的路由，
+    // 数据库配置变化或尚未连接时重新连接并应用迁移
+    pub async fn reconfigure(&self) -> CustomResult<()> {
+        let running = config::Config::current();
+        let config = config::Config::reload()?;
+        if config.init.sql
+            && (self.db.lock().await.is_none()
+                || toml::to_string(&config.sql_config)? != toml::to_string(&running.sql_config)?)
+        {
+            self.sql_link(&config.sql_config).await?;
+            self.sql_get().await?.migrate(false).await?;
+        }
+        config::Config::set_current(config);
+        Ok(())
     }
 
-    pub async fn trigger_restart(&self) -> CustomResult<()> {
-        *self.restart_progress.lock().await = true;
-        if let Ok(db) = self.sql_get().await {
-            db.get_db().close().await?;
+    // 轮询配置文件的修改时间，变化时热重载
+    pub async fn watch(self: Arc<Self>) {
+        let mut last = config::Config::modified();
+        let mut interval = tokio::time::interval(Duration::from_secs(2));
+        loop {
+            interval.tick().await;
+            let current = config::Config::modified();
+            if current == last {
+                continue;
+            }
+            last = current;
+            match self.reconfigure().await {
+                Ok(()) => println!("配置已重新加载"),
+                Err(e) => eprintln!("配置重新加载失败，继续使用原配置: {}", e),
+            }
         }
-        self.shutdown
-            .lock()
-            .await
-            .take()
-            .ok_or_else(|| "未能获取rocket的shutdown".into_custom_error())?
-            .notify();
-        Ok(())
     }
 }
 
@@ -83,55 +137,122 @@ fn cors() -> Cors {
     .expect("CORS配置错误")
 }
 
+// 挂载接口，启动服务和接口测试共用，调试接口另行挂载
+fn mount(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
+    rocket
+        .mount("/", api::setup_routes())
+        .mount("/auth/token", api::jwt_routes())
+        .mount("/auth/mfa", api::mfa_routes())
+        .mount("/auth/password", api::password_routes())
+        .mount("/auth/email", api::email_routes())
+        .mount("/auth/oidc", api::oidc_routes())
+        .mount("/field", api::fields_routes())
+        .mount("/admin", api::admin_routes())
+        .register("/", api::catchers())
+}
+
+// 启动时自动应用未执行的迁移，预览和回滚使用 db migrate 子命令
+async fn migrate(db: &sql::Database) -> CustomResult<()> {
+    for plan in db.migrate(false).await? {
+        println!("已执行迁移: {:04} {}", plan.version, plan.name);
+    }
+    Ok(())
+}
+
 #[rocket::main]
 async fn main() -> CustomResult<()> {
-    let config = config::Config::read().unwrap_or_else(|e| {
+    match &common::cli::args().command {
+        None | Some(common::cli::Command::Serve) => {}
+        Some(command) => std::process::exit(command::run(command).await),
+    }
+
+    let config = config::Config::load().unwrap_or_else(|e| {
         eprintln!("配置读取失败: {}", e);
-        config::Config::default()
+        std::process::exit(1);
     });
+    config::Config::set_current(config.clone());
     let state = Arc::new(AppState::new());
 
     let rocket_config = rocket::Config::figment()
-        .merge(("address", config.address))
-        .merge(("port", config.port));
+        .merge(("address", config.address.clone()))
+        .merge(("port", config.port))
+        .merge(("shutdown.grace", config.shutdown.grace))
+        .merge(("shutdown.mercy", config.shutdown.mercy));
 
     let mut rocket_builder = rocket::build()
         .configure(rocket_config)
         .manage(state.clone())
-        .attach(cors());
+        .attach(cors())
+        .attach(AdHoc::on_shutdown("关闭日志", |_| {
+            Box::pin(async {
+                println!("收到关闭信号，停止接收新请求并等待进行中的请求完成");
+            })
+        }));
 
-    rocket_builder = rocket_builder.mount("/", routes![api::setup::get_step]);
+    if !config.init.administrator {
+        let token = security::install::ensure_token()?;
+        println!("安装令牌: {}", token);
+        println!("完成安装前请在安装页面填写该令牌，令牌同时保存在 assets/install_token");
+    }
 
-    if !config.init.sql {
-        rocket_builder = rocket_builder.mount("/", routes![api::setup::setup_sql]);
-    } else if !config.init.administrator {
-        rocket_builder = rocket_builder.mount("/", routes![api::setup::setup_account]);
-    } else {
+    if config.init.sql {
         state.sql_link(&config.sql_config).await?;
-        rocket_builder = rocket_builder.mount("/auth/token", api::jwt_routes());
-        rocket_builder = rocket_builder.mount("/field", api::fields_routes());
+        migrate(&state.sql_get().await?).await?;
+    }
+
+    rocket_builder = mount(rocket_builder);
+
+    if config.dev_mode {
+        eprintln!("警告: 开发模式已启用，调试接口 /auth/token/test 无需认证即可签发管理员令牌，请勿在生产环境中使用");
+        rocket_builder = rocket_builder.mount("/auth/token", api::debug_routes());
     }
 
     let rocket = rocket_builder.ignite().await?;
 
-    rocket
-        .state::<Arc<AppState>>()
-        .ok_or_else(|| "无法获取AppState".into_custom_error())?
-        .set_shutdown(rocket.shutdown())
-        .await;
+    let watcher = tokio::spawn(state.clone().watch());
 
     rocket.launch().await?;
+    let started = Instant::now();
+    watcher.abort();
 
-    if *state.restart_progress.lock().await {
-        if let Ok(current_exe) = std::env::current_exe() {
-            match std::process::Command::new(current_exe).spawn() {
-                Ok(_) => println!("成功启动新进程"),
-                Err(e) => eprintln!("启动新进程失败: {}", e),
-            };
-        } else {
-            eprintln!("获取当前可执行文件路径失败");
-        }
+    match state
+        .shutdown(Duration::from_secs(config.shutdown.grace.into()))
+        .await
+    {
+        Ok(()) => println!("已安全关闭，资源清理用时 {:?}", started.elapsed()),
+        Err(e) => eprintln!("关闭过程中出错: {}", e),
     }
 
     std::process::exit(0);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::testing;
+    use crate::api::users::{self, Role};
+
+    #[tokio::test]
+    async fn reconfigure_relinks_database_but_keeps_listener() {
+        let site = testing::site(|_| {}).await;
+        site.add_user("alice", "alice@example.com", Role::Administrator)
+            .await;
+        let state = AppState::new();
+        *state.db.lock().await = Some(site.sql.clone());
+
+        let mut file = (*config::Config::current()).clone();
+        file.port = 9999;
+        file.sql_config.sqlite.path = ":memory:".to_string();
+        config::Config::write(file).unwrap();
+        let result = state.reconfigure().await;
+        std::fs::remove_file(config::Config::get_path().unwrap()).unwrap();
+        result.unwrap();
+
+        // 端口需要重启才生效，数据库配置变化后切换到新的连接并完成迁移
+        let current = config::Config::current();
+        assert_eq!(current.port, config::Config::default().port);
+        assert_eq!(current.sql_config.sqlite.path, ":memory:");
+        let sql = state.sql_get().await.unwrap();
+        assert!(users::snapshot_user(&sql, "alice").await.unwrap().is_none());
+    }
+}
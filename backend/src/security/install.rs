@@ -0,0 +1,44 @@
+use crate::common::error::CustomResult;
+use crate::common::helpers;
+use std::{env, fs, path::PathBuf};
+
+fn get_token_path() -> CustomResult<PathBuf> {
+    Ok(env::current_dir()?.join("assets").join("install_token"))
+}
+
+// 安装完成前的一次性令牌，已存在时沿用，重启不会让已打印的令牌失效
+pub fn ensure_token() -> CustomResult<String> {
+    let path = get_token_path()?;
+    if let Ok(token) = fs::read_to_string(&path) {
+        let token = token.trim();
+        if !token.is_empty() {
+            return Ok(token.to_string());
+        }
+    }
+
+    let token = helpers::generate_random_string(32);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, &token)?;
+    Ok(token)
+}
+
+pub fn verify_token(token: &str) -> bool {
+    let Some(expected) = get_token_path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+    else {
+        return false;
+    };
+    let expected = expected.trim();
+    !expected.is_empty() && helpers::constant_time_eq(expected.as_bytes(), token.as_bytes())
+}
+
+pub fn remove_token() -> CustomResult<()> {
+    let path = get_token_path()?;
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
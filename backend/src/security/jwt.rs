@@ -56,6 +56,20 @@ pub fn generate_key() -> CustomResult<()> {
     Ok(())
 }
 
+pub fn key_exists() -> CustomResult<bool> {
+    Ok(get_key_path(&SecretKey::Signing)?.exists() && get_key_path(&SecretKey::Verifying)?.exists())
+}
+
+pub fn remove_key() -> CustomResult<()> {
+    for key_type in [SecretKey::Signing, SecretKey::Verifying] {
+        let path = get_key_path(&key_type)?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
 pub fn get_key(key_type: SecretKey) -> CustomResult<[u8; 32]> {
     let key_bytes = fs::read(get_key_path(&key_type)?)?;
     let mut key = [0u8; 32];
@@ -1,2 +1,4 @@
-pub mod bcrypt;
+pub mod install;
 pub mod jwt;
+pub mod password;
+pub mod totp;
@@ -0,0 +1,129 @@
+use crate::common::config::{self, PasswordConfig};
+use crate::common::error::{CustomError, CustomResult, ErrorKind};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use std::collections::HashSet;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+struct Blocklist {
+    path: String,
+    modified: Option<SystemTime>,
+    words: Arc<HashSet<String>>,
+}
+
+static BLOCKLIST: RwLock<Option<Blocklist>> = RwLock::new(None);
+
+// 列表文件可能很大，按路径和修改时间缓存
+fn blocklist(path: &str) -> CustomResult<Arc<HashSet<String>>> {
+    let modified = fs::metadata(path)?.modified().ok();
+    if let Some(cached) = BLOCKLIST.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if cached.path == path && cached.modified == modified {
+            return Ok(cached.words.clone());
+        }
+    }
+
+    let words = Arc::new(
+        fs::read_to_string(path)?
+            .lines()
+            .map(|line| line.trim().to_lowercase())
+            .filter(|line| !line.is_empty())
+            .collect::<HashSet<_>>(),
+    );
+    *BLOCKLIST.write().unwrap_or_else(|e| e.into_inner()) = Some(Blocklist {
+        path: path.to_string(),
+        modified,
+        words: words.clone(),
+    });
+    Ok(words)
+}
+
+// 设置新密码前调用，登录时不检查，以免规则收紧后旧密码无法登录
+pub fn check_policy(password: &str) -> CustomResult<()> {
+    let config = config::Config::current().password.clone();
+    if password.chars().count() < config.min_length as usize {
+        return Err(CustomError::localized(ErrorKind::Validation, "password.too_short").with_arg("min", config.min_length));
+    }
+    if !config.blocklist.is_empty()
+        && blocklist(&config.blocklist)?.contains(&password.to_lowercase())
+    {
+        return Err(CustomError::localized(ErrorKind::Validation, "password.too_common"));
+    }
+    Ok(())
+}
+
+fn argon2(config: &PasswordConfig) -> CustomResult<Argon2<'static>> {
+    let params = Params::new(
+        config.argon2.memory,
+        config.argon2.iterations,
+        config.argon2.parallelism,
+        None,
+    )?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+pub fn generate_hash(password: &str) -> CustomResult<String> {
+    let config = config::Config::current().password.clone();
+    match config.algorithm.to_lowercase().as_str() {
+        "argon2id" => {
+            let salt = SaltString::generate(&mut OsRng);
+            Ok(argon2(&config)?
+                .hash_password(password.as_bytes(), &salt)?
+                .to_string())
+        }
+        _ => Ok(bcrypt::hash(password, config.bcrypt_cost)?),
+    }
+}
+
+// 按哈希自身记录的算法和参数校验，兼容修改配置前生成的哈希
+pub fn verify_hash(password: &str, hash: &str) -> CustomResult<()> {
+    let valid = if hash.starts_with("$argon2") {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &PasswordHash::new(hash)?)
+            .is_ok()
+    } else {
+        bcrypt::verify(password, hash)?
+    };
+    valid
+        .then_some(())
+        .ok_or_else(|| CustomError::localized(ErrorKind::Unauthorized, "auth.invalid_credentials"))
+}
+
+static DUMMY_HASH: RwLock<Option<String>> = RwLock::new(None);
+
+// 用户不存在时用于校验的哈希，与真实账户花费相同的时间，避免按响应时间判断账户是否存在
+pub fn dummy_hash() -> CustomResult<String> {
+    if let Some(hash) = DUMMY_HASH.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if !needs_rehash(hash) {
+            return Ok(hash.clone());
+        }
+    }
+    let hash = generate_hash(&crate::common::helpers::generate_random_string(32))?;
+    *DUMMY_HASH.write().unwrap_or_else(|e| e.into_inner()) = Some(hash.clone());
+    Ok(hash)
+}
+
+// 哈希的算法或参数与当前配置不一致时需要在登录成功后重新计算
+pub fn needs_rehash(hash: &str) -> bool {
+    let config = config::Config::current().password.clone();
+    match config.algorithm.to_lowercase().as_str() {
+        "argon2id" => {
+            let Ok(parsed) = PasswordHash::new(hash) else {
+                return true;
+            };
+            let Ok(params) = Params::try_from(&parsed) else {
+                return true;
+            };
+            parsed.algorithm != Algorithm::Argon2id.ident()
+                || parsed.version != Some(Version::V0x13.into())
+                || params.m_cost() != config.argon2.memory
+                || params.t_cost() != config.argon2.iterations
+                || params.p_cost() != config.argon2.parallelism
+        }
+        _ => hash
+            .parse::<bcrypt::HashParts>()
+            .map(|parts| parts.get_cost() != config.bcrypt_cost)
+            .unwrap_or(true),
+    }
+}
@@ -0,0 +1,64 @@
+use crate::common::error::{CustomErrorInto, CustomResult};
+use crate::common::helpers;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const ISSUER: &str = "Echoes";
+const DIGITS: usize = 6;
+const STEP: u64 = 30;
+// 允许前后各一个时间步的时钟误差
+const SKEW: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+fn build(secret: &str, account: &str) -> CustomResult<TOTP> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|_| "两步验证密钥无效".into_custom_error())?;
+    Ok(TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        SKEW as u8,
+        STEP,
+        bytes,
+        Some(ISSUER.to_string()),
+        account.replace(':', "_"),
+    )?)
+}
+
+// Base32 编码的 160 位随机密钥
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Secret::Raw(bytes.to_vec()).to_encoded().to_string()
+}
+
+// otpauth:// 格式，可直接生成二维码供验证器扫描
+pub fn provisioning_uri(secret: &str, account: &str) -> CustomResult<String> {
+    Ok(build(secret, account)?.get_url())
+}
+
+// 验证通过时返回匹配的时间步，调用方据此拒绝重复使用同一个验证码
+pub fn verify(secret: &str, code: &str, now: u64) -> CustomResult<Option<i64>> {
+    let totp = build(secret, "")?;
+    let current = now / STEP;
+    Ok((current.saturating_sub(SKEW)..=current + SKEW)
+        .find(|step| helpers::constant_time_eq(totp.generate(step * STEP).as_bytes(), code.trim().as_bytes()))
+        .map(|step| step as i64))
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| helpers::generate_random_string(10).to_lowercase())
+        .collect()
+}
+
+// 恢复码只存储哈希，忽略大小写和分隔符
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
@@ -345,6 +345,10 @@ impl QueryBuilder {
         self
     }
 
+    pub fn is_read(&self) -> bool {
+        self.operation == SqlOperation::Select
+    }
+
     pub fn build(&self) -> CustomResult<(String, Vec<SafeValue>)> {
         let mut query = String::new();
         let mut params = Vec::new();
@@ -358,7 +362,9 @@ impl QueryBuilder {
 
         if let Some(where_clause) = &self.where_clause {
             query.push_str(" WHERE ");
-            let (where_sql, where_params) = self.build_where_clause(where_clause)?;
+            // UPDATE 的 SET 已占用前面的占位符序号
+            let (where_sql, where_params) =
+                self.build_where_clause(where_clause, params.len() + 1)?;
             query.push_str(&where_sql);
             params.extend(where_params);
         }
@@ -432,9 +438,13 @@ impl QueryBuilder {
         Ok(())
     }
 
-    fn build_where_clause(&self, clause: &WhereClause) -> CustomResult<(String, Vec<SafeValue>)> {
+    fn build_where_clause(
+        &self,
+        clause: &WhereClause,
+        start_index: usize,
+    ) -> CustomResult<(String, Vec<SafeValue>)> {
         let mut params = Vec::new();
-        let mut param_index = 1; // 添加参数索引计数器
+        let mut param_index = start_index; // 添加参数索引计数器
 
         let sql = match clause {
             WhereClause::And(conditions) => {
@@ -483,7 +493,7 @@ impl QueryBuilder {
                 self.build_condition(condition, &mut params, start_index)?
             }
             _ => {
-                let (sql, params_inner) = self.build_where_clause(clause)?;
+                let (sql, params_inner) = self.build_where_clause(clause, start_index)?;
                 params = params_inner;
                 sql
             }
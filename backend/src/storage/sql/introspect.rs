@@ -0,0 +1,171 @@
+use super::{migration, Database, DatabaseTrait, DatabaseType};
+use crate::common::error::CustomResult;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Default)]
+pub struct LiveTable {
+    pub columns: BTreeMap<String, String>,
+    pub indexes: BTreeSet<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColumnRef {
+    pub table: String,
+    pub column: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexRef {
+    pub table: String,
+    pub index: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TypeMismatch {
+    pub table: String,
+    pub column: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DriftReport {
+    pub db_type: String,
+    pub drifted: bool,
+    pub missing_tables: Vec<String>,
+    pub missing_columns: Vec<ColumnRef>,
+    pub missing_indexes: Vec<IndexRef>,
+    pub type_mismatches: Vec<TypeMismatch>,
+}
+
+fn catalog_queries(db_type: DatabaseType) -> (&'static str, &'static str) {
+    match db_type {
+        DatabaseType::PostgreSQL => (
+            "SELECT table_name::text AS table_name, column_name::text AS column_name, \
+             CASE WHEN character_maximum_length IS NULL THEN data_type::text \
+             ELSE data_type::text || '(' || character_maximum_length::text || ')' END AS data_type \
+             FROM information_schema.columns WHERE table_schema = current_schema()",
+            "SELECT tablename::text AS table_name, indexname::text AS index_name \
+             FROM pg_indexes WHERE schemaname = current_schema()",
+        ),
+        DatabaseType::MySQL => (
+            "SELECT CAST(TABLE_NAME AS CHAR) AS table_name, CAST(COLUMN_NAME AS CHAR) AS column_name, \
+             CAST(COLUMN_TYPE AS CHAR) AS data_type \
+             FROM information_schema.COLUMNS WHERE TABLE_SCHEMA = DATABASE()",
+            "SELECT DISTINCT CAST(TABLE_NAME AS CHAR) AS table_name, CAST(INDEX_NAME AS CHAR) AS index_name \
+             FROM information_schema.STATISTICS WHERE TABLE_SCHEMA = DATABASE()",
+        ),
+        DatabaseType::SQLite => (
+            "SELECT m.name AS table_name, p.name AS column_name, p.type AS data_type \
+             FROM sqlite_master m JOIN pragma_table_info(m.name) p WHERE m.type = 'table'",
+            "SELECT tbl_name AS table_name, name AS index_name \
+             FROM sqlite_master WHERE type = 'index'",
+        ),
+    }
+}
+
+// 当前库（模式）中的全部表名，查询失败时返回错误而不是当作表不存在
+pub async fn table_names(
+    db: &dyn DatabaseTrait,
+    db_type: DatabaseType,
+) -> CustomResult<BTreeSet<String>> {
+    let (columns_query, _) = catalog_queries(db_type);
+    Ok(db
+        .execute_raw(columns_query, Vec::new())
+        .await?
+        .iter()
+        .filter_map(|row| row.get("table_name").and_then(|value| value.as_str()))
+        .map(str::to_string)
+        .collect())
+}
+
+// 读取当前数据库中带前缀的表、字段和索引
+pub async fn read_catalog(sql: &Database) -> CustomResult<BTreeMap<String, LiveTable>> {
+    let (columns_query, indexes_query) = catalog_queries(sql.get_type());
+    let mut tables: BTreeMap<String, LiveTable> = BTreeMap::new();
+
+    let text = |row: &std::collections::HashMap<String, serde_json::Value>, key: &str| {
+        row.get(key)
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    for row in sql.get_db().execute_raw(columns_query, Vec::new()).await? {
+        let table = text(&row, "table_name");
+        if !table.starts_with(sql.get_prefix()) {
+            continue;
+        }
+        tables
+            .entry(table)
+            .or_default()
+            .columns
+            .insert(text(&row, "column_name"), text(&row, "data_type"));
+    }
+
+    for row in sql.get_db().execute_raw(indexes_query, Vec::new()).await? {
+        if let Some(table) = tables.get_mut(&text(&row, "table_name")) {
+            table.indexes.insert(text(&row, "index_name"));
+        }
+    }
+
+    Ok(tables)
+}
+
+pub async fn detect_drift(sql: &Database) -> CustomResult<DriftReport> {
+    let db_type = sql.get_type();
+    let expected = migration::expected_schema(sql.get_prefix(), db_type)?;
+    let live = read_catalog(sql).await?;
+
+    let mut report = DriftReport {
+        db_type: db_type.to_string(),
+        drifted: false,
+        missing_tables: Vec::new(),
+        missing_columns: Vec::new(),
+        missing_indexes: Vec::new(),
+        type_mismatches: Vec::new(),
+    };
+
+    for table in expected.tables() {
+        let name = table.name.as_str().to_string();
+        let Some(live_table) = live.get(&name) else {
+            report.missing_tables.push(name);
+            continue;
+        };
+
+        for field in &table.fields {
+            match live_table.columns.get(field.name.as_str()) {
+                None => report.missing_columns.push(ColumnRef {
+                    table: name.clone(),
+                    column: field.name.as_str().to_string(),
+                }),
+                Some(actual) if !field.field_type.matches(db_type, actual) => {
+                    report.type_mismatches.push(TypeMismatch {
+                        table: name.clone(),
+                        column: field.name.as_str().to_string(),
+                        expected: field.field_type_sql(db_type)?,
+                        actual: actual.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+
+        for index in &table.indexes {
+            if !live_table.indexes.contains(index.name.as_str()) {
+                report.missing_indexes.push(IndexRef {
+                    table: name.clone(),
+                    index: index.name.as_str().to_string(),
+                });
+            }
+        }
+    }
+
+    report.drifted = !(report.missing_tables.is_empty()
+        && report.missing_columns.is_empty()
+        && report.missing_indexes.is_empty()
+        && report.type_mismatches.is_empty());
+
+    Ok(report)
+}
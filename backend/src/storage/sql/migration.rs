@@ -0,0 +1,550 @@
+use super::builder::{self, SafeValue, SqlOperation, ValidationLevel};
+use super::schema::{self, AlterTable, Field, FieldConstraint, FieldType, SchemaBuilder, Table};
+use super::{introspect, DatabaseTrait, DatabaseType};
+use crate::common::error::{CustomErrorInto, CustomResult};
+
+#[derive(Debug, Clone)]
+pub enum MigrationStep {
+    Create(Table),
+    Drop(String),
+    Alter(AlterTable),
+}
+
+impl MigrationStep {
+    // 生成SQL，并同步更新内存中的表结构
+    pub fn apply(&self, schema: &mut SchemaBuilder, db_type: DatabaseType) -> CustomResult<String> {
+        match self {
+            MigrationStep::Create(table) => {
+                let sql = table.to_sql(db_type)?;
+                schema.add_table(table.clone())?;
+                Ok(sql)
+            }
+            MigrationStep::Drop(table) => Ok(schema.remove_table(table)?.drop_sql()),
+            MigrationStep::Alter(alter) => {
+                let table = schema.table_mut(alter.name.as_str())?;
+                let sql = alter.to_sql(db_type, table)?;
+                alter.apply(table)?;
+                Ok(sql)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: Vec<MigrationStep>,
+    pub down: Vec<MigrationStep>,
+}
+
+impl Migration {
+    pub fn up(&self, schema: &mut SchemaBuilder, db_type: DatabaseType) -> CustomResult<String> {
+        Self::apply(&self.up, schema, db_type)
+    }
+
+    pub fn down(&self, schema: &mut SchemaBuilder, db_type: DatabaseType) -> CustomResult<String> {
+        Self::apply(&self.down, schema, db_type)
+    }
+
+    fn apply(
+        steps: &[MigrationStep],
+        schema: &mut SchemaBuilder,
+        db_type: DatabaseType,
+    ) -> CustomResult<String> {
+        let statements = steps
+            .iter()
+            .map(|step| step.apply(schema, db_type))
+            .collect::<CustomResult<Vec<_>>>()?;
+        Ok(statements.join("\n\n"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationPlan {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: String,
+}
+
+impl std::fmt::Display for MigrationPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "-- {:04} {}\n{}", self.version, self.name, self.sql)
+    }
+}
+
+// 所有迁移按版本号升序排列，已发布的迁移不能再修改
+pub fn migrations(db_prefix: &str) -> CustomResult<Vec<Migration>> {
+    let initial = schema::generate_schema(SafeValue::Text(
+        db_prefix.to_string(),
+        ValidationLevel::Strict,
+    ))?;
+
+    let api_keys = schema::api_keys_table(db_prefix)?;
+    let login_attempts = schema::login_attempts_table(db_prefix)?;
+    let user_totp = schema::user_totp_table(db_prefix)?;
+    let user_tokens = schema::user_tokens_table(db_prefix)?;
+    let user_identities = schema::user_identities_table(db_prefix)?;
+    let oidc_states = schema::oidc_states_table(db_prefix)?;
+    let users = format!("{}users", db_prefix);
+    let mut add_email_verified = AlterTable::new(&users)?;
+    add_email_verified.add_column(schema::email_verified_field()?);
+    let mut drop_email_verified = AlterTable::new(&users)?;
+    drop_email_verified.drop_column("email_verified_at")?;
+
+    Ok(vec![
+        Migration {
+            version: 1,
+            name: "initial_schema",
+            up: initial
+                .tables()
+                .iter()
+                .cloned()
+                .map(MigrationStep::Create)
+                .collect(),
+            down: initial
+                .tables()
+                .iter()
+                .rev()
+                .map(|table| MigrationStep::Drop(table.name.as_str().to_string()))
+                .collect(),
+        },
+        Migration {
+            version: 2,
+            name: "api_keys",
+            down: vec![MigrationStep::Drop(api_keys.name.as_str().to_string())],
+            up: vec![MigrationStep::Create(api_keys)],
+        },
+        Migration {
+            version: 3,
+            name: "login_attempts",
+            down: vec![MigrationStep::Drop(login_attempts.name.as_str().to_string())],
+            up: vec![MigrationStep::Create(login_attempts)],
+        },
+        Migration {
+            version: 4,
+            name: "user_totp",
+            down: vec![MigrationStep::Drop(user_totp.name.as_str().to_string())],
+            up: vec![MigrationStep::Create(user_totp)],
+        },
+        Migration {
+            version: 5,
+            name: "email_tokens",
+            down: vec![
+                MigrationStep::Drop(user_tokens.name.as_str().to_string()),
+                MigrationStep::Alter(drop_email_verified),
+            ],
+            up: vec![
+                MigrationStep::Alter(add_email_verified),
+                MigrationStep::Create(user_tokens),
+            ],
+        },
+        Migration {
+            version: 6,
+            name: "oidc",
+            down: vec![
+                MigrationStep::Drop(oidc_states.name.as_str().to_string()),
+                MigrationStep::Drop(user_identities.name.as_str().to_string()),
+            ],
+            up: vec![
+                MigrationStep::Create(user_identities),
+                MigrationStep::Create(oidc_states),
+            ],
+        },
+    ])
+}
+
+// 依次应用所有迁移得到的目标表结构
+pub fn expected_schema(db_prefix: &str, db_type: DatabaseType) -> CustomResult<SchemaBuilder> {
+    let mut schema = SchemaBuilder::new();
+    for migration in migrations(db_prefix)? {
+        migration.up(&mut schema, db_type)?;
+    }
+    Ok(schema)
+}
+
+pub struct Migrator<'a> {
+    db: &'a dyn DatabaseTrait,
+    db_type: DatabaseType,
+    prefix: String,
+}
+
+impl<'a> Migrator<'a> {
+    pub fn new(db: &'a dyn DatabaseTrait, db_type: DatabaseType, prefix: &str) -> Self {
+        Self {
+            db,
+            db_type,
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn table_name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    fn migrations_table(&self) -> CustomResult<Table> {
+        let mut table = Table::new(&self.table_name("migrations"))?;
+        table
+            .if_not_exists()
+            .add_field(Field::new(
+                "version",
+                FieldType::BigInt,
+                FieldConstraint::new().primary(),
+            )?)
+            .add_field(Field::new(
+                "name",
+                FieldType::VarChar(100),
+                FieldConstraint::new().not_null(),
+            )?)
+            .add_field(Field::new(
+                "applied_at",
+                FieldType::Timestamp,
+                FieldConstraint::new().not_null().default(SafeValue::Text(
+                    "CURRENT_TIMESTAMP".to_string(),
+                    ValidationLevel::Strict,
+                )),
+            )?);
+        Ok(table)
+    }
+
+    async fn table_exists(&self, name: &str) -> CustomResult<bool> {
+        Ok(introspect::table_names(self.db, self.db_type)
+            .await?
+            .contains(&self.table_name(name)))
+    }
+
+    pub async fn applied_versions(&self) -> CustomResult<Vec<i64>> {
+        if !self.table_exists("migrations").await? {
+            return Ok(Vec::new());
+        }
+        let mut builder = builder::QueryBuilder::new(
+            SqlOperation::Select,
+            self.table_name("migrations"),
+            self.db_type,
+        )?;
+        builder.add_field("version".to_string())?;
+        let mut versions = self
+            .db
+            .execute_query(&builder)
+            .await?
+            .iter()
+            .filter_map(|row| row.get("version").and_then(|v| v.as_i64()))
+            .collect::<Vec<_>>();
+        versions.sort();
+        Ok(versions)
+    }
+
+    fn record_sql(&self, migration: &Migration) -> CustomResult<String> {
+        Ok(format!(
+            "INSERT INTO {} (version, name) VALUES ({}, '{}');",
+            self.table_name("migrations"),
+            migration.version,
+            SafeValue::Text(migration.name.to_string(), ValidationLevel::Strict).to_string()?
+        ))
+    }
+
+    fn unrecord_sql(&self, migration: &Migration) -> String {
+        format!(
+            "DELETE FROM {} WHERE version = {};",
+            self.table_name("migrations"),
+            migration.version
+        )
+    }
+
+    // 迁移功能上线前安装的数据库已经有初始表结构，只需登记版本
+    async fn baseline(&self, dry_run: bool) -> CustomResult<Vec<i64>> {
+        let applied = self.applied_versions().await?;
+        if !applied.is_empty() || !self.table_exists("users").await? {
+            return Ok(applied);
+        }
+        let initial = migrations(&self.prefix)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| "缺少初始迁移".into_custom_error())?;
+        if !dry_run {
+            self.db
+                .execute_batch(&format!(
+                    "{}\n\n{}\n\n{}",
+                    self.baseline_rebuild_sql(&initial)?,
+                    self.migrations_table()?.to_sql(self.db_type)?,
+                    self.record_sql(&initial)?
+                ))
+                .await?;
+        }
+        Ok(vec![initial.version])
+    }
+
+    // 迁移功能上线前 SQLite 建表时遗漏了非自增的单列主键（如 users.username），
+    // 启用外键后写入引用这些表的子表会报 foreign key mismatch，登记版本时按初始定义重建
+    fn baseline_rebuild_sql(&self, initial: &Migration) -> CustomResult<String> {
+        if self.db_type != DatabaseType::SQLite {
+            return Ok(String::new());
+        }
+        let mut schema = SchemaBuilder::new();
+        initial.up(&mut schema, self.db_type)?;
+        let missing = schema
+            .tables()
+            .iter()
+            .filter(|table| match table.primary_keys.as_slice() {
+                [key] => table
+                    .field(key)
+                    .is_some_and(|field| field.field_type != FieldType::Integer(true)),
+                _ => false,
+            })
+            .map(|table| table.name.as_str().to_string())
+            .collect::<Vec<_>>();
+
+        let statements = missing
+            .iter()
+            .map(|name| {
+                let mut alter = AlterTable::new(name)?;
+                alter.rebuild();
+                MigrationStep::Alter(alter).apply(&mut schema, self.db_type)
+            })
+            .collect::<CustomResult<Vec<_>>>()?;
+        Ok(statements.join("\n\n"))
+    }
+
+    pub async fn run(&self, dry_run: bool) -> CustomResult<Vec<MigrationPlan>> {
+        let applied = self.baseline(dry_run).await?;
+        let mut schema = SchemaBuilder::new();
+        let mut plans = Vec::new();
+
+        for migration in migrations(&self.prefix)? {
+            let sql = migration.up(&mut schema, self.db_type)?;
+            if applied.contains(&migration.version) {
+                continue;
+            }
+            if !dry_run {
+                self.db
+                    .execute_batch(&format!(
+                        "{}\n\n{}\n\n{}",
+                        self.migrations_table()?.to_sql(self.db_type)?,
+                        sql,
+                        self.record_sql(&migration)?
+                    ))
+                    .await?;
+            }
+            plans.push(MigrationPlan {
+                version: migration.version,
+                name: migration.name,
+                sql,
+            });
+        }
+
+        Ok(plans)
+    }
+
+    // 删除当前前缀下由迁移创建的表
+    async fn reset(&self) -> CustomResult<()> {
+        let mut statements = expected_schema(&self.prefix, self.db_type)?
+            .tables()
+            .iter()
+            .rev()
+            .map(|table| format!("DROP TABLE IF EXISTS {};", table.name.as_str()))
+            .collect::<Vec<_>>();
+        statements.push(format!(
+            "DROP TABLE IF EXISTS {};",
+            self.table_name("migrations")
+        ));
+        self.db.execute_batch(&statements.join("\n")).await
+    }
+
+    // 安装时调用：有迁移记录说明是上次未完成的安装，继续执行；
+    // 已有同前缀的表但没有迁移记录时，除非 overwrite 否则拒绝覆盖
+    pub async fn install(&self, overwrite: bool) -> CustomResult<Vec<MigrationPlan>> {
+        let applied = self.applied_versions().await?;
+        let existing = !applied.is_empty() || self.table_exists("users").await?;
+        if existing && overwrite {
+            self.reset().await?;
+        } else if existing && applied.is_empty() {
+            return Err(format!(
+                "数据库中已存在 {} 表，如需覆盖请使用 overwrite",
+                self.table_name("users")
+            )
+            .into_custom_error());
+        }
+        self.run(false).await
+    }
+
+    pub async fn rollback(&self, target: i64, dry_run: bool) -> CustomResult<Vec<MigrationPlan>> {
+        let applied = self.applied_versions().await?;
+        let migrations = migrations(&self.prefix)?
+            .into_iter()
+            .filter(|migration| applied.contains(&migration.version))
+            .collect::<Vec<_>>();
+
+        let mut schema = SchemaBuilder::new();
+        for migration in &migrations {
+            migration.up(&mut schema, self.db_type)?;
+        }
+
+        let mut plans = Vec::new();
+        for migration in migrations.into_iter().rev() {
+            if migration.version <= target {
+                break;
+            }
+            let sql = migration.down(&mut schema, self.db_type)?;
+            if !dry_run {
+                self.db
+                    .execute_batch(&format!("{}\n\n{}", sql, self.unrecord_sql(&migration)))
+                    .await?;
+            }
+            plans.push(MigrationPlan {
+                version: migration.version,
+                name: migration.name,
+                sql,
+            });
+        }
+
+        Ok(plans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sql::memory_database;
+
+    #[test]
+    fn versions_are_strictly_ascending() {
+        let versions = migrations("echoes_")
+            .unwrap()
+            .iter()
+            .map(|migration| migration.version)
+            .collect::<Vec<_>>();
+        assert_eq!(versions.first(), Some(&1));
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn dry_run_plans_in_order_without_writing() {
+        let sql = memory_database().await;
+        let expected = migrations(sql.get_prefix())
+            .unwrap()
+            .iter()
+            .map(|migration| migration.version)
+            .collect::<Vec<_>>();
+
+        let plans = sql.migrate(true).await.unwrap();
+        assert_eq!(
+            plans.iter().map(|plan| plan.version).collect::<Vec<_>>(),
+            expected
+        );
+        assert!(plans[0].to_string().starts_with("-- 0001 "));
+        assert!(plans[0].sql.contains("CREATE TABLE echoes_users"));
+        let tables = introspect::table_names(sql.get_db().as_ref(), sql.get_type())
+            .await
+            .unwrap();
+        assert!(tables.is_empty());
+
+        sql.migrate(false).await.unwrap();
+        assert_eq!(sql.migrator().applied_versions().await.unwrap(), expected);
+        assert!(sql.migrate(true).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rollback_reverses_order() {
+        let sql = memory_database().await;
+        sql.migrate(false).await.unwrap();
+        let plans = sql.migrator().rollback(1, true).await.unwrap();
+        let versions = plans.iter().map(|plan| plan.version).collect::<Vec<_>>();
+        assert!(versions.windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(versions.last(), Some(&2));
+    }
+
+    // 连接不可用时不能当作没有迁移记录，否则会从第一个版本重新执行
+    #[tokio::test]
+    async fn applied_versions_propagates_query_errors() {
+        let sql = memory_database().await;
+        sql.migrate(false).await.unwrap();
+        sql.close().await.unwrap();
+        assert!(sql.migrator().applied_versions().await.is_err());
+    }
+
+    // 迁移功能上线前的 SQLite 建表语句，非自增的单列主键只保留 NOT NULL
+    fn legacy_sqlite_ddl(prefix: &str) -> String {
+        let initial =
+            schema::generate_schema(SafeValue::Text(prefix.to_string(), ValidationLevel::Strict))
+                .unwrap();
+        initial
+            .tables()
+            .iter()
+            .map(|table| {
+                let mut table = table.clone();
+                if let [key] = table.primary_keys.clone().as_slice() {
+                    let field = table.field_mut(key).unwrap();
+                    if field.field_type != FieldType::Integer(true) {
+                        field.constraints.is_primary = false;
+                        table.primary_keys.clear();
+                    }
+                }
+                table.to_sql(DatabaseType::SQLite).unwrap()
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    #[tokio::test]
+    async fn baseline_rebuilds_sqlite_tables_without_primary_keys() {
+        // 旧版本安装时没有启用外键，按同样的方式创建数据库后再以默认配置连接
+        let path = std::env::temp_dir().join(format!("echoes-baseline-{}.db", std::process::id()));
+        std::fs::File::create(&path).unwrap();
+        let mut config = crate::common::config::SqlConfig::default();
+        config.sqlite.path = path.display().to_string();
+        config.sqlite.foreign_keys = false;
+        let legacy = super::super::Database::link(&config).await.unwrap();
+        legacy
+            .get_db()
+            .execute_batch(&legacy_sqlite_ddl(legacy.get_prefix()))
+            .await
+            .unwrap();
+        legacy
+            .get_db()
+            .execute_raw(
+                &format!(
+                    "INSERT INTO {} (username, email, password_hash, role) VALUES ('alice', 'alice@example.com', 'x', 'administrator')",
+                    legacy.table_name("users")
+                ),
+                vec![],
+            )
+            .await
+            .unwrap();
+        legacy.close().await.unwrap();
+
+        config.sqlite.foreign_keys = true;
+        let sql = super::super::Database::link(&config).await.unwrap();
+        let db = sql.get_db();
+        let plans = sql.migrate(false).await.unwrap();
+        assert!(plans.iter().all(|plan| plan.version > 1));
+
+        // 重建后保留原有数据，引用 users 的子表可以正常写入
+        let post = format!(
+            "INSERT INTO {} (author_name, title, content, status) VALUES ('alice', 'hello', 'world', 'draft')",
+            sql.table_name("posts")
+        );
+        db.execute_raw(&post, vec![]).await.unwrap();
+        db.execute_raw(
+            &format!(
+                "INSERT INTO {} (username, secret, confirmed_at, last_step, recovery_codes) VALUES ('alice', 'x', 0, 0, '')",
+                sql.table_name("user_totp")
+            ),
+            vec![],
+        )
+        .await
+        .unwrap();
+        let duplicate = db
+            .execute_raw(
+                &format!(
+                    "INSERT INTO {} (username, email, password_hash, role) VALUES ('alice', 'other@example.com', 'x', 'administrator')",
+                    sql.table_name("users")
+                ),
+                vec![],
+            )
+            .await;
+        assert!(duplicate.is_err());
+
+        sql.close().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,13 +1,25 @@
 pub mod builder;
+pub mod introspect;
+pub mod migration;
 mod mysql;
 mod postgresql;
 mod schema;
 mod sqllite;
 
-use crate::common::error::{CustomErrorInto, CustomResult};
+use crate::common::error::{CustomError, CustomErrorInto, CustomResult, ErrorKind};
+use crate::common::i18n::Locale;
 use crate::config;
 use async_trait::async_trait;
-use std::{collections::HashMap, sync::Arc};
+use serde::Serialize;
+use sqlx::pool::PoolOptions;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DatabaseType {
@@ -26,6 +38,43 @@ impl std::fmt::Display for DatabaseType {
     }
 }
 
+// 三种数据库共用的连接池参数
+fn pool_options<DB: sqlx::Database>(config: &config::SqlConfig) -> PoolOptions<DB> {
+    let optional = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+    PoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout))
+        .idle_timeout(optional(config.idle_timeout))
+        .max_lifetime(optional(config.max_lifetime))
+}
+
+// 拼接 PostgreSQL/MySQL 连接串，各部分按百分号编码，with_db 为 false 时不指定数据库
+fn connection_url(config: &config::SqlConfig, scheme: &str, with_db: bool) -> CustomResult<String> {
+    let invalid = |part: &str| format!("无效的数据库{}", part).into_custom_error();
+    let mut url = url::Url::parse(&format!("{}://localhost", scheme))?;
+    url.set_host(Some(&config.host))?;
+    url.set_port(u16::try_from(config.port).ok().filter(|port| *port > 0))
+        .map_err(|_| invalid("端口"))?;
+    url.set_username(&config.user)
+        .map_err(|_| invalid("用户名"))?;
+    url.set_password(Some(&config.password))
+        .map_err(|_| invalid("密码"))?;
+    if with_db {
+        url.path_segments_mut()
+            .map_err(|_| invalid("名称"))?
+            .push(&config.db_name);
+    }
+
+    if !config.ssl_mode.is_empty() {
+        url.query_pairs_mut().append_pair("sslmode", &config.ssl_mode);
+    }
+    for (key, value) in &config.options {
+        url.query_pairs_mut().append_pair(key, value);
+    }
+    Ok(url.into())
+}
+
 #[async_trait]
 pub trait DatabaseTrait: Send + Sync {
     async fn connect(database: &config::SqlConfig, db: bool) -> CustomResult<Self>
@@ -35,17 +84,55 @@ pub trait DatabaseTrait: Send + Sync {
         &'a self,
         builder: &builder::QueryBuilder,
     ) -> CustomResult<Vec<HashMap<String, serde_json::Value>>>;
-    async fn initialization(database: config::SqlConfig) -> CustomResult<()>
+    async fn execute_raw(
+        &self,
+        query: &str,
+        values: Vec<builder::SafeValue>,
+    ) -> CustomResult<Vec<HashMap<String, serde_json::Value>>>;
+    // 执行写操作，返回匹配的行数
+    async fn execute_affected(&self, builder: &builder::QueryBuilder) -> CustomResult<u64>;
+    async fn execute_batch(&self, sql: &str) -> CustomResult<()>;
+    async fn initialization(database: config::SqlConfig, overwrite: bool) -> CustomResult<()>
+    where
+        Self: Sized;
+    async fn probe(database: &config::SqlConfig) -> CustomResult<ProbeReport>
     where
         Self: Sized;
     async fn close(&self) -> CustomResult<()>;
 }
 
+// 安装前的连接测试结果，不会创建或修改任何数据
+#[derive(Debug, Default, Serialize)]
+pub struct ProbeReport {
+    pub ok: bool,
+    pub db_type: String,
+    pub server_version: String,
+    pub database_exists: bool,
+    pub can_create_database: bool,
+    pub can_create_table: bool,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+// 取查询结果第一行的文本字段
+fn first_text(rows: &[HashMap<String, serde_json::Value>], key: &str) -> Option<String> {
+    rows.first()
+        .and_then(|row| row.get(key))
+        .and_then(|value| value.as_str())
+        .map(str::to_string)
+}
+
 #[derive(Clone)]
 pub struct Database {
     pub db: Arc<Box<dyn DatabaseTrait>>,
+    // 只读副本及其 host:port，用于日志
+    pub replicas: Arc<Vec<(String, Box<dyn DatabaseTrait>)>>,
     pub prefix: Arc<String>,
     pub db_type: Arc<DatabaseType>,
+    next_replica: Arc<AtomicUsize>,
+    force_primary: bool,
+    // 当前会话是否已经写入过，写入后的读取都走主库
+    written: Arc<AtomicBool>,
 }
 
 impl Database {
@@ -65,33 +152,279 @@ impl Database {
         *self.db_type.clone()
     }
 
-    pub async fn link(database: &config::SqlConfig) -> CustomResult<Self> {
-        let db: Box<dyn DatabaseTrait> = match database.db_type.to_lowercase().as_str() {
+    async fn connect(database: &config::SqlConfig) -> CustomResult<Box<dyn DatabaseTrait>> {
+        Ok(match database.db_type.to_lowercase().as_str() {
             "postgresql" => Box::new(postgresql::Postgresql::connect(database, true).await?),
             "mysql" => Box::new(mysql::Mysql::connect(database, true).await?),
             "sqllite" => Box::new(sqllite::Sqlite::connect(database, true).await?),
             _ => return Err("unknown database type".into_custom_error()),
+        })
+    }
+
+    pub async fn link(database: &config::SqlConfig) -> CustomResult<Self> {
+        let db_type = match database.db_type.to_lowercase().as_str() {
+            "postgresql" => DatabaseType::PostgreSQL,
+            "mysql" => DatabaseType::MySQL,
+            "sqllite" => DatabaseType::SQLite,
+            _ => return Err("unknown database type".into_custom_error()),
         };
+        if db_type == DatabaseType::SQLite && !database.replicas.is_empty() {
+            return Err(CustomError::localized(
+                ErrorKind::Internal,
+                "config.sqlite_replicas",
+            ));
+        }
+
+        let db = Self::connect(database).await?;
+        let mut replicas = Vec::new();
+        for replica in &database.replicas {
+            let mut replica_config = database.clone();
+            replica_config.host = replica.host.clone();
+            replica_config.port = replica.port;
+            let label = format!("{}:{}", replica.host, replica.port);
+            let db = Self::connect(&replica_config).await.map_err(|e| {
+                CustomError::localized(ErrorKind::Internal, "database.replica_failed")
+                    .with_arg("label", &label)
+                    .with_arg("error", e)
+            })?;
+            replicas.push((label, db));
+        }
 
         Ok(Self {
             db: Arc::new(db),
+            replicas: Arc::new(replicas),
             prefix: Arc::new(database.db_prefix.clone()),
-            db_type: Arc::new(match database.db_type.to_lowercase().as_str() {
-                "postgresql" => DatabaseType::PostgreSQL,
-                "mysql" => DatabaseType::MySQL,
-                "sqllite" => DatabaseType::SQLite,
-                _ => return Err("unknown database type".into_custom_error()),
-            }),
+            db_type: Arc::new(db_type),
+            next_replica: Arc::new(AtomicUsize::new(0)),
+            force_primary: false,
+            written: Arc::new(AtomicBool::new(false)),
         })
     }
 
-    pub async fn initial_setup(database: config::SqlConfig) -> CustomResult<()> {
+    // 每个请求从一个新会话开始，同一会话中写入后的读取自动走主库，
+    // 保证请求内能读到自己的写入；读取其他请求刚写入的数据仍需 primary()
+    pub fn session(&self) -> Self {
+        Self {
+            force_primary: false,
+            written: Arc::new(AtomicBool::new(false)),
+            ..self.clone()
+        }
+    }
+
+    // 返回一个所有查询都走主库的副本，用于刚写入后需要立即读到结果的场景
+    pub fn primary(&self) -> Self {
+        Self {
+            force_primary: true,
+            ..self.clone()
+        }
+    }
+
+    fn reader(&self) -> Option<&(String, Box<dyn DatabaseTrait>)> {
+        if self.force_primary || self.written.load(Ordering::Relaxed) || self.replicas.is_empty()
+        {
+            return None;
+        }
+        let index = self.next_replica.fetch_add(1, Ordering::Relaxed) % self.replicas.len();
+        self.replicas.get(index)
+    }
+
+    // SELECT 轮询分发到只读副本，副本出错时回退主库；写操作始终走主库
+    pub async fn execute_query(
+        &self,
+        builder: &builder::QueryBuilder,
+    ) -> CustomResult<Vec<HashMap<String, serde_json::Value>>> {
+        if !builder.is_read() {
+            self.written.store(true, Ordering::Relaxed);
+        } else if let Some((label, replica)) = self.reader() {
+            match replica.execute_query(builder).await {
+                Ok(rows) => return Ok(rows),
+                Err(e) => eprintln!("只读副本 {} 查询失败，改用主库: {}", label, e),
+            }
+        }
+        self.db.execute_query(builder).await
+    }
+
+    pub async fn execute_affected(&self, builder: &builder::QueryBuilder) -> CustomResult<u64> {
+        self.written.store(true, Ordering::Relaxed);
+        self.db.execute_affected(builder).await
+    }
+
+    // 构建器无法表达的语句（如 upsert），始终在主库执行
+    pub async fn execute_raw(
+        &self,
+        query: &str,
+        values: Vec<builder::SafeValue>,
+    ) -> CustomResult<Vec<HashMap<String, serde_json::Value>>> {
+        self.written.store(true, Ordering::Relaxed);
+        self.db.execute_raw(query, values).await
+    }
+
+    pub async fn close(&self) -> CustomResult<()> {
+        for (_, replica) in self.replicas.iter() {
+            replica.close().await?;
+        }
+        self.db.close().await
+    }
+
+    pub fn migrator(&self) -> migration::Migrator<'_> {
+        migration::Migrator::new(
+            self.db.as_ref().as_ref(),
+            self.get_type(),
+            self.get_prefix(),
+        )
+    }
+
+    pub async fn migrate(&self, dry_run: bool) -> CustomResult<Vec<migration::MigrationPlan>> {
+        self.migrator().run(dry_run).await
+    }
+
+    pub async fn rollback(
+        &self,
+        target: i64,
+        dry_run: bool,
+    ) -> CustomResult<Vec<migration::MigrationPlan>> {
+        self.migrator().rollback(target, dry_run).await
+    }
+
+    // 错误和警告按调用方的语言输出
+    pub async fn probe(database: &config::SqlConfig, locale: Locale) -> ProbeReport {
+        let result = match database.validate() {
+            Err(e) => Err(e),
+            Ok(()) => match database.db_type.to_lowercase().as_str() {
+                "postgresql" => postgresql::Postgresql::probe(database).await,
+                "mysql" => mysql::Mysql::probe(database).await,
+                _ => sqllite::Sqlite::probe(database).await,
+            },
+        };
+
+        let mut report = result.unwrap_or_else(|e| ProbeReport {
+            error: Some(e.message(locale)),
+            ..Default::default()
+        });
+        report.db_type = database.db_type.clone();
+
+        if report.error.is_none() {
+            if report.database_exists {
+                report
+                    .warnings
+                    .push(locale.format("database.exists", &[("name", database.db_name.clone())]));
+            } else if !report.can_create_database {
+                report
+                    .warnings
+                    .push(locale.text("database.cannot_create_database"));
+            }
+            if !report.can_create_table {
+                report
+                    .warnings
+                    .push(locale.text("database.cannot_create_table"));
+            }
+        }
+        report.ok = report.error.is_none() && report.warnings.is_empty();
+        report
+    }
+
+    pub async fn initial_setup(database: config::SqlConfig, overwrite: bool) -> CustomResult<()> {
         match database.db_type.to_lowercase().as_str() {
-            "postgresql" => postgresql::Postgresql::initialization(database).await?,
-            "mysql" => mysql::Mysql::initialization(database).await?,
-            "sqllite" => sqllite::Sqlite::initialization(database).await?,
+            "postgresql" => postgresql::Postgresql::initialization(database, overwrite).await?,
+            "mysql" => mysql::Mysql::initialization(database, overwrite).await?,
+            "sqllite" => sqllite::Sqlite::initialization(database, overwrite).await?,
             _ => return Err("unknown database type".into_custom_error()),
         };
         Ok(())
     }
 }
+
+// 测试用的内存数据库，每次调用得到一个独立的库
+#[cfg(test)]
+pub async fn memory_database() -> Database {
+    let mut database = config::SqlConfig::default();
+    database.sqlite.path = ":memory:".to_string();
+    Database::link(&database).await.expect("内存数据库连接失败")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::builder::{QueryBuilder, SafeValue, SqlOperation, ValidationLevel};
+    use super::*;
+
+    async fn notes(body: &str) -> Box<dyn DatabaseTrait> {
+        let mut database = config::SqlConfig::default();
+        database.sqlite.path = ":memory:".to_string();
+        let db = Database::connect(&database).await.unwrap();
+        db.execute_batch(&format!(
+            "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT);\n\
+             INSERT INTO notes (id, body) VALUES (1, '{}');",
+            body
+        ))
+        .await
+        .unwrap();
+        db
+    }
+
+    // 主库和副本各自独立，通过读到的内容判断查询走了哪个库
+    async fn with_replica(replica: Box<dyn DatabaseTrait>) -> Database {
+        let primary = memory_database().await;
+        Database {
+            db: Arc::new(notes("primary").await),
+            replicas: Arc::new(vec![("replica:5432".to_string(), replica)]),
+            ..primary
+        }
+    }
+
+    async fn body(sql: &Database) -> String {
+        let mut builder =
+            QueryBuilder::new(SqlOperation::Select, "notes".to_string(), sql.get_type()).unwrap();
+        builder.add_field("body".to_string()).unwrap();
+        let rows = sql.execute_query(&builder).await.unwrap();
+        first_text(&rows, "body").unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_stick_to_primary_after_write() {
+        let sql = with_replica(notes("replica").await).await.session();
+        assert_eq!(body(&sql).await, "replica");
+        assert_eq!(body(&sql.primary()).await, "primary");
+
+        let mut builder =
+            QueryBuilder::new(SqlOperation::Update, "notes".to_string(), sql.get_type()).unwrap();
+        builder
+            .set_value(
+                "body".to_string(),
+                SafeValue::Text("updated".to_string(), ValidationLevel::Standard),
+            )
+            .unwrap();
+        assert_eq!(sql.execute_affected(&builder).await.unwrap(), 1);
+
+        // 同一会话中写入后读主库，新会话重新分发到副本
+        assert_eq!(body(&sql).await, "updated");
+        assert_eq!(body(&sql.clone()).await, "updated");
+        assert_eq!(body(&sql.session()).await, "replica");
+    }
+
+    #[tokio::test]
+    async fn replica_errors_fall_back_to_primary() {
+        let mut database = config::SqlConfig::default();
+        database.sqlite.path = ":memory:".to_string();
+        let empty = Database::connect(&database).await.unwrap();
+        let sql = with_replica(empty).await.session();
+        assert_eq!(body(&sql).await, "primary");
+    }
+
+    #[tokio::test]
+    async fn probe_reports_in_the_requested_language() {
+        let database = config::SqlConfig {
+            acquire_timeout: 0,
+            ..Default::default()
+        };
+        let report = Database::probe(&database, Locale::En).await;
+        assert_eq!(
+            report.error.as_deref(),
+            Some("sql_config.acquire_timeout must be greater than 0")
+        );
+        let report = Database::probe(&database, Locale::ZhCn).await;
+        assert_eq!(
+            report.error.as_deref(),
+            Some("sql_config.acquire_timeout 必须大于 0")
+        );
+    }
+}
@@ -1,8 +1,8 @@
 use super::{
     builder::{self, SafeValue},
-    schema, DatabaseTrait,
+    first_text, migration, DatabaseTrait, DatabaseType, ProbeReport,
 };
-use crate::common::error::{CustomErrorInto, CustomResult};
+use crate::common::error::{CustomError, CustomResult, ErrorKind};
 use crate::config;
 use async_trait::async_trait;
 use serde_json::Value;
@@ -14,36 +14,42 @@ pub struct Mysql {
     pool: MySqlPool,
 }
 
+fn bind_values(
+    query: &str,
+    values: Vec<SafeValue>,
+) -> sqlx::query::Query<'_, sqlx::MySql, sqlx::mysql::MySqlArguments> {
+    let mut sqlx_query = sqlx::query(query);
+    for value in values {
+        match value {
+            SafeValue::Null => sqlx_query = sqlx_query.bind(None::<String>),
+            SafeValue::Bool(b) => sqlx_query = sqlx_query.bind(b),
+            SafeValue::Integer(i) => sqlx_query = sqlx_query.bind(i),
+            SafeValue::Float(f) => sqlx_query = sqlx_query.bind(f),
+            SafeValue::Text(s, _) => sqlx_query = sqlx_query.bind(s),
+            SafeValue::DateTime(dt) => sqlx_query = sqlx_query.bind(dt.to_rfc3339()),
+        }
+    }
+    sqlx_query
+}
+
 #[async_trait]
 impl DatabaseTrait for Mysql {
     async fn connect(db_config: &config::SqlConfig, db: bool) -> CustomResult<Self> {
-        let connection_str;
-        if db {
-            connection_str = format!(
-                "mysql://{}:{}@{}:{}/{}",
-                db_config.user,
-                db_config.password,
-                db_config.host,
-                db_config.port,
-                db_config.db_name
-            );
-        } else {
-            connection_str = format!(
-                "mysql://{}:{}@{}:{}",
-                db_config.user, db_config.password, db_config.host, db_config.port
-            );
-        }
+        let connection_str = super::connection_url(db_config, "mysql", db)?;
 
         let pool = tokio::time::timeout(
-            std::time::Duration::from_secs(5),
-            MySqlPool::connect(&connection_str),
+            std::time::Duration::from_secs(db_config.acquire_timeout),
+            super::pool_options::<sqlx::MySql>(db_config).connect(&connection_str),
         )
         .await
-        .map_err(|_| "连接超时".into_custom_error())??;
+        .map_err(|_| CustomError::localized(ErrorKind::Internal, "database.connect_timeout"))??;
 
         if let Err(e) = pool.acquire().await {
             pool.close().await;
-            return Err(format!("数据库连接测试失败: {}", e).into_custom_error());
+            return Err(
+                CustomError::localized(ErrorKind::Internal, "database.connect_failed")
+                    .with_arg("error", e),
+            );
         }
 
         Ok(Mysql { pool })
@@ -53,19 +59,16 @@ impl DatabaseTrait for Mysql {
         builder: &builder::QueryBuilder,
     ) -> CustomResult<Vec<HashMap<String, Value>>> {
         let (query, values) = builder.build()?;
+        self.execute_raw(&query, values).await
+    }
+
+    async fn execute_raw(
+        &self,
+        query: &str,
+        values: Vec<SafeValue>,
+    ) -> CustomResult<Vec<HashMap<String, Value>>> {
         println!("查询语句: {}", query);
-        let mut sqlx_query = sqlx::query(&query);
-
-        for value in values {
-            match value {
-                SafeValue::Null => sqlx_query = sqlx_query.bind(None::<String>),
-                SafeValue::Bool(b) => sqlx_query = sqlx_query.bind(b),
-                SafeValue::Integer(i) => sqlx_query = sqlx_query.bind(i),
-                SafeValue::Float(f) => sqlx_query = sqlx_query.bind(f),
-                SafeValue::Text(s, _) => sqlx_query = sqlx_query.bind(s),
-                SafeValue::DateTime(dt) => sqlx_query = sqlx_query.bind(dt.to_rfc3339()),
-            }
-        }
+        let sqlx_query = bind_values(query, values);
 
         let rows = sqlx_query.fetch_all(&self.pool).await?;
         println!("查询结果: {:?}", rows);
@@ -77,16 +80,16 @@ impl DatabaseTrait for Mysql {
                     .iter()
                     .map(|col| {
                         let value = match col.type_info().name() {
-                            "INT4" | "INT8" => Value::Number(
+                            "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "BIGINT" => Value::Number(
                                 row.try_get::<i64, _>(col.name()).unwrap_or_default().into(),
                             ),
-                            "FLOAT4" | "FLOAT8" => Value::Number(
+                            "FLOAT" | "DOUBLE" => Value::Number(
                                 serde_json::Number::from_f64(
                                     row.try_get::<f64, _>(col.name()).unwrap_or(0.0),
                                 )
                                 .unwrap_or_else(|| 0.into()),
                             ),
-                            "BOOL" => Value::Bool(row.try_get(col.name()).unwrap_or_default()),
+                            "BOOLEAN" => Value::Bool(row.try_get(col.name()).unwrap_or_default()),
                             _ => Value::String(row.try_get(col.name()).unwrap_or_default()),
                         };
                         (col.name().to_string(), value)
@@ -96,32 +99,127 @@ impl DatabaseTrait for Mysql {
             .collect())
     }
 
-    async fn initialization(db_config: config::SqlConfig) -> CustomResult<()> {
+    async fn execute_affected(&self, builder: &builder::QueryBuilder) -> CustomResult<u64> {
+        let (query, values) = builder.build()?;
+        Ok(bind_values(&query, values)
+            .execute(&self.pool)
+            .await?
+            .rows_affected())
+    }
+
+    async fn execute_batch(&self, sql: &str) -> CustomResult<()> {
+        let mut tx = self.pool.begin().await?;
+        tx.execute(sql).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn initialization(db_config: config::SqlConfig, overwrite: bool) -> CustomResult<()> {
         let db_prefix = SafeValue::Text(
             format!("{}", db_config.db_prefix),
             builder::ValidationLevel::Strict,
         );
-        let grammar = schema::generate_schema(super::DatabaseType::MySQL, db_prefix)?;
-
         let pool = Self::connect(&db_config, false).await?.pool;
 
-        pool.execute(format!("CREATE DATABASE `{}`", db_config.db_name).as_str())
-            .await?;
-        pool.execute(
-            format!(
-                "ALTER DATABASE `{}` DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci",
-                db_config.db_name
+        let exists = sqlx::query("SELECT 1 FROM information_schema.SCHEMATA WHERE SCHEMA_NAME = ?")
+            .bind(&db_config.db_name)
+            .fetch_optional(&pool)
+            .await?
+            .is_some();
+        if !exists {
+            pool.execute(format!("CREATE DATABASE `{}`", db_config.db_name).as_str())
+                .await?;
+            pool.execute(
+                format!(
+                    "ALTER DATABASE `{}` DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci",
+                    db_config.db_name
+                )
+                .as_str(),
             )
-            .as_str(),
-        )
-        .await?;
+            .await?;
+        }
+
+        let result = async {
+            let db = Self::connect(&db_config, true).await?;
+            let result = migration::Migrator::new(&db, DatabaseType::MySQL, &db_prefix.to_string()?)
+                .install(overwrite)
+                .await;
+            db.close().await?;
+            result
+        }
+        .await;
 
-        let new_pool = Self::connect(&db_config, true).await?.pool;
+        // 本次新建的数据库在失败时删除，避免留下不完整的安装
+        if result.is_err() && !exists {
+            pool.execute(format!("DROP DATABASE IF EXISTS `{}`", db_config.db_name).as_str())
+                .await?;
+        }
+        pool.close().await;
+        result.map(|_| ())
+    }
 
-        new_pool.execute(grammar.as_str()).await?;
-        new_pool.close().await;
-        Ok(())
+    async fn probe(db_config: &config::SqlConfig) -> CustomResult<ProbeReport> {
+        let db = Self::connect(db_config, false).await?;
+        let result = async {
+            // USER_PRIVILEGES 中的 GRANTEE 格式为 'user'@'host'
+            let grantee = "CONCAT('''', REPLACE(CURRENT_USER(), '@', '''@'''), '''')";
+            let db_name = || {
+                vec![SafeValue::Text(
+                    db_config.db_name.clone(),
+                    builder::ValidationLevel::Raw,
+                )]
+            };
+            let version = db
+                .execute_raw("SELECT CAST(VERSION() AS CHAR) AS version", Vec::new())
+                .await?;
+            let global = db
+                .execute_raw(
+                    &format!(
+                        "SELECT CAST(PRIVILEGE_TYPE AS CHAR) AS privilege \
+                         FROM information_schema.USER_PRIVILEGES WHERE GRANTEE = {}",
+                        grantee
+                    ),
+                    Vec::new(),
+                )
+                .await?;
+            let schema = db
+                .execute_raw(
+                    &format!(
+                        "SELECT CAST(PRIVILEGE_TYPE AS CHAR) AS privilege \
+                         FROM information_schema.SCHEMA_PRIVILEGES \
+                         WHERE GRANTEE = {} AND TABLE_SCHEMA = ?",
+                        grantee
+                    ),
+                    db_name(),
+                )
+                .await?;
+            let database = db
+                .execute_raw(
+                    "SELECT CAST(SCHEMA_NAME AS CHAR) AS name \
+                     FROM information_schema.SCHEMATA WHERE SCHEMA_NAME = ?",
+                    db_name(),
+                )
+                .await?;
+
+            let has_create = |rows: &[HashMap<String, Value>]| {
+                rows.iter().any(|row| {
+                    row.get("privilege").and_then(|value| value.as_str()) == Some("CREATE")
+                })
+            };
+            let can_create_database = has_create(&global);
+            Ok(ProbeReport {
+                server_version: first_text(&version, "version").unwrap_or_default(),
+                database_exists: !database.is_empty(),
+                can_create_database,
+                can_create_table: can_create_database || has_create(&schema),
+                ..Default::default()
+            })
+        }
+        .await;
+        db.close().await?;
+        result
     }
+
     async fn close(&self) -> CustomResult<()> {
         self.pool.close().await;
         while !self.pool.is_closed() {
@@ -1,8 +1,8 @@
 use super::{
     builder::{self, SafeValue},
-    schema, DatabaseTrait,
+    first_text, migration, DatabaseTrait, DatabaseType, ProbeReport,
 };
-use crate::common::error::{CustomErrorInto, CustomResult};
+use crate::common::error::{CustomError, CustomResult, ErrorKind};
 use crate::config;
 use async_trait::async_trait;
 use serde_json::Value;
@@ -14,36 +14,49 @@ pub struct Postgresql {
     pool: PgPool,
 }
 
+fn bind_values(
+    query: &str,
+    values: Vec<SafeValue>,
+) -> sqlx::query::Query<'_, sqlx::Postgres, sqlx::postgres::PgArguments> {
+    let mut sqlx_query = sqlx::query(query);
+    for value in values {
+        match value {
+            SafeValue::Null => sqlx_query = sqlx_query.bind(None::<String>),
+            SafeValue::Bool(b) => sqlx_query = sqlx_query.bind(b),
+            SafeValue::Integer(i) => sqlx_query = sqlx_query.bind(i),
+            SafeValue::Float(f) => sqlx_query = sqlx_query.bind(f),
+            SafeValue::Text(s, _) => sqlx_query = sqlx_query.bind(s),
+            SafeValue::DateTime(dt) => sqlx_query = sqlx_query.bind(dt.to_rfc3339()),
+        }
+    }
+    sqlx_query
+}
+
 #[async_trait]
 impl DatabaseTrait for Postgresql {
     async fn connect(db_config: &config::SqlConfig, db: bool) -> CustomResult<Self> {
-        let connection_str;
-        if db {
-            connection_str = format!(
-                "postgres://{}:{}@{}:{}/{}",
-                db_config.user,
-                db_config.password,
-                db_config.host,
-                db_config.port,
-                db_config.db_name
-            );
+        // 不指定数据库时连接默认的 postgres 维护库，否则会尝试连接与用户同名的数据库
+        let connection_str = if db {
+            super::connection_url(db_config, "postgres", true)?
         } else {
-            connection_str = format!(
-                "postgres://{}:{}@{}:{}",
-                db_config.user, db_config.password, db_config.host, db_config.port
-            );
-        }
+            let mut maintenance = db_config.clone();
+            maintenance.db_name = "postgres".to_string();
+            super::connection_url(&maintenance, "postgres", true)?
+        };
 
         let pool = tokio::time::timeout(
-            std::time::Duration::from_secs(5),
-            PgPool::connect(&connection_str),
+            std::time::Duration::from_secs(db_config.acquire_timeout),
+            super::pool_options::<sqlx::Postgres>(db_config).connect(&connection_str),
         )
         .await
-        .map_err(|_| "连接超时".into_custom_error())??;
+        .map_err(|_| CustomError::localized(ErrorKind::Internal, "database.connect_timeout"))??;
 
         if let Err(e) = pool.acquire().await {
             pool.close().await;
-            return Err(format!("数据库连接测试失败: {}", e).into_custom_error());
+            return Err(
+                CustomError::localized(ErrorKind::Internal, "database.connect_failed")
+                    .with_arg("error", e),
+            );
         }
 
         Ok(Postgresql { pool })
@@ -54,19 +67,16 @@ impl DatabaseTrait for Postgresql {
         builder: &builder::QueryBuilder,
     ) -> CustomResult<Vec<HashMap<String, Value>>> {
         let (query, values) = builder.build()?;
+        self.execute_raw(&query, values).await
+    }
 
-        let mut sqlx_query = sqlx::query(&query);
-
-        for value in values {
-            match value {
-                SafeValue::Null => sqlx_query = sqlx_query.bind(None::<String>),
-                SafeValue::Bool(b) => sqlx_query = sqlx_query.bind(b),
-                SafeValue::Integer(i) => sqlx_query = sqlx_query.bind(i),
-                SafeValue::Float(f) => sqlx_query = sqlx_query.bind(f),
-                SafeValue::Text(s, _) => sqlx_query = sqlx_query.bind(s),
-                SafeValue::DateTime(dt) => sqlx_query = sqlx_query.bind(dt.to_rfc3339()),
-            }
-        }
+    async fn execute_raw(
+        &self,
+        query: &str,
+        values: Vec<SafeValue>,
+    ) -> CustomResult<Vec<HashMap<String, Value>>> {
+
+        let sqlx_query = bind_values(query, values);
 
         let rows = sqlx_query.fetch_all(&self.pool).await?;
 
@@ -96,24 +106,99 @@ impl DatabaseTrait for Postgresql {
             .collect())
     }
 
-    async fn initialization(db_config: config::SqlConfig) -> CustomResult<()> {
+    async fn execute_affected(&self, builder: &builder::QueryBuilder) -> CustomResult<u64> {
+        let (query, values) = builder.build()?;
+        Ok(bind_values(&query, values)
+            .execute(&self.pool)
+            .await?
+            .rows_affected())
+    }
+
+    async fn execute_batch(&self, sql: &str) -> CustomResult<()> {
+        let mut tx = self.pool.begin().await?;
+        tx.execute(sql).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn initialization(db_config: config::SqlConfig, overwrite: bool) -> CustomResult<()> {
         let db_prefix = SafeValue::Text(
             format!("{}", db_config.db_prefix),
             builder::ValidationLevel::Strict,
         );
-        let grammar = schema::generate_schema(super::DatabaseType::PostgreSQL, db_prefix)?;
-
         let pool = Self::connect(&db_config, false).await?.pool;
 
-        pool.execute(format!("CREATE DATABASE {}", db_config.db_name).as_str())
-            .await?;
+        let exists = sqlx::query("SELECT 1 FROM pg_database WHERE datname = $1")
+            .bind(&db_config.db_name)
+            .fetch_optional(&pool)
+            .await?
+            .is_some();
+        if !exists {
+            pool.execute(format!("CREATE DATABASE {}", db_config.db_name).as_str())
+                .await?;
+        }
 
-        let new_pool = Self::connect(&db_config, true).await?.pool;
+        let result = async {
+            let db = Self::connect(&db_config, true).await?;
+            let result =
+                migration::Migrator::new(&db, DatabaseType::PostgreSQL, &db_prefix.to_string()?)
+                    .install(overwrite)
+                    .await;
+            db.close().await?;
+            result
+        }
+        .await;
 
-        new_pool.execute(grammar.as_str()).await?;
-        new_pool.close().await;
+        // 本次新建的数据库在失败时删除，避免留下不完整的安装
+        if result.is_err() && !exists {
+            pool.execute(format!("DROP DATABASE IF EXISTS {}", db_config.db_name).as_str())
+                .await?;
+        }
+        pool.close().await;
+        result.map(|_| ())
+    }
 
-        Ok(())
+    async fn probe(db_config: &config::SqlConfig) -> CustomResult<ProbeReport> {
+        let db = Self::connect(db_config, false).await?;
+        let result = async {
+            let version = db
+                .execute_raw("SELECT version() AS version", Vec::new())
+                .await?;
+            let role = db
+                .execute_raw(
+                    "SELECT CASE WHEN rolcreatedb OR rolsuper THEN 'yes' ELSE 'no' END AS can_create \
+                     FROM pg_roles WHERE rolname = current_user",
+                    Vec::new(),
+                )
+                .await?;
+            let database = db
+                .execute_raw(
+                    "SELECT CASE WHEN has_database_privilege(current_user, datname, 'CREATE') \
+                     THEN 'yes' ELSE 'no' END AS can_create_table \
+                     FROM pg_database WHERE datname = $1",
+                    vec![SafeValue::Text(
+                        db_config.db_name.clone(),
+                        builder::ValidationLevel::Raw,
+                    )],
+                )
+                .await?;
+
+            let can_create_database = first_text(&role, "can_create").as_deref() == Some("yes");
+            Ok(ProbeReport {
+                server_version: first_text(&version, "version").unwrap_or_default(),
+                database_exists: !database.is_empty(),
+                can_create_database,
+                // 新建的数据库归当前用户所有，可以建表
+                can_create_table: match first_text(&database, "can_create_table") {
+                    Some(privilege) => privilege == "yes",
+                    None => can_create_database,
+                },
+                ..Default::default()
+            })
+        }
+        .await;
+        db.close().await?;
+        result
     }
 
     async fn close(&self) -> CustomResult<()> {
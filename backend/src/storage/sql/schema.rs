@@ -13,6 +13,31 @@ pub enum FieldType {
     Timestamp,
 }
 
+impl FieldType {
+    // 与数据库目录中读取到的类型比较
+    pub fn matches(&self, db_type: DatabaseType, live: &str) -> bool {
+        let live = live.trim().to_lowercase();
+        match (self, db_type) {
+            (FieldType::Integer(_), DatabaseType::MySQL) => {
+                live == "int" || live.starts_with("int(")
+            }
+            (FieldType::Integer(_), _) => live == "integer",
+            (FieldType::BigInt, _) => live == "bigint" || live.starts_with("bigint("),
+            (FieldType::VarChar(size), DatabaseType::PostgreSQL) => {
+                live == format!("character varying({})", size)
+            }
+            (FieldType::VarChar(size), _) => live == format!("varchar({})", size),
+            (FieldType::Text, _) => live == "text",
+            (FieldType::Boolean, DatabaseType::PostgreSQL) => live == "boolean",
+            (FieldType::Boolean, DatabaseType::MySQL) => live == "tinyint(1)",
+            (FieldType::Boolean, DatabaseType::SQLite) => live == "integer",
+            (FieldType::Timestamp, DatabaseType::PostgreSQL) => live == "timestamp with time zone",
+            (FieldType::Timestamp, DatabaseType::MySQL) => live == "timestamp",
+            (FieldType::Timestamp, DatabaseType::SQLite) => live == "text",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FieldConstraint {
     pub is_primary: bool,
@@ -40,6 +65,22 @@ pub struct ForeignKey {
     pub on_update: Option<ForeignKeyAction>,
 }
 
+impl ForeignKey {
+    pub fn to_sql(&self) -> String {
+        let mut sql = format!(" REFERENCES {}({})", self.ref_table, self.ref_column);
+
+        if let Some(on_delete) = &self.on_delete {
+            sql.push_str(&format!(" ON DELETE {}", on_delete));
+        }
+
+        if let Some(on_update) = &self.on_update {
+            sql.push_str(&format!(" ON UPDATE {}", on_update));
+        }
+
+        sql
+    }
+}
+
 impl Display for ForeignKeyAction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
@@ -67,6 +108,7 @@ pub struct Table {
     pub fields: Vec<Field>,
     pub indexes: Vec<Index>,
     pub primary_keys: Vec<String>,
+    pub if_not_exists: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -152,7 +194,7 @@ impl Field {
         })
     }
 
-    fn field_type_sql(&self, db_type: DatabaseType) -> CustomResult<String> {
+    pub fn field_type_sql(&self, db_type: DatabaseType) -> CustomResult<String> {
         Ok(match &self.field_type {
             FieldType::Integer(auto_increment) => {
                 if *auto_increment && self.constraints.is_primary {
@@ -252,20 +294,11 @@ impl Field {
         if self.constraints.is_unique {
             sql.push_str(" UNIQUE");
         }
-        if self.constraints.is_primary && db_type == DatabaseType::SQLite {
-            match &self.field_type {
-                FieldType::Integer(true) => {
-                    sql.push_str(" PRIMARY KEY AUTOINCREMENT");
-                }
-                _ => {}
-            }
-        } else if self.constraints.is_primary {
+        // 自增已经包含在字段类型中，复合主键由表级约束声明
+        if self.constraints.is_primary {
             match (db_type, &self.field_type) {
-                (DatabaseType::MySQL, FieldType::Integer(true)) => {
-                    sql.push_str(" PRIMARY KEY AUTO_INCREMENT");
-                }
-                (DatabaseType::PostgreSQL, FieldType::Integer(true)) => {
-                    sql.push_str(" PRIMARY KEY GENERATED ALWAYS AS IDENTITY");
+                (DatabaseType::SQLite, FieldType::Integer(true)) => {
+                    sql.push_str(" PRIMARY KEY AUTOINCREMENT");
                 }
                 _ => sql.push_str(" PRIMARY KEY"),
             }
@@ -278,15 +311,7 @@ impl Field {
             sql.push_str(&format!(" CHECK ({})", check_sql));
         }
         if let Some(fk) = &self.constraints.foreign_key {
-            sql.push_str(&format!(" REFERENCES {}({})", fk.ref_table, fk.ref_column));
-
-            if let Some(on_delete) = &fk.on_delete {
-                sql.push_str(&format!(" ON DELETE {}", on_delete.to_string()));
-            }
-
-            if let Some(on_update) = &fk.on_update {
-                sql.push_str(&format!(" ON UPDATE {}", on_update.to_string()));
-            }
+            sql.push_str(&fk.to_sql());
         }
 
         Ok(sql)
@@ -300,9 +325,15 @@ impl Table {
             fields: Vec::new(),
             indexes: Vec::new(),
             primary_keys: Vec::new(),
+            if_not_exists: false,
         })
     }
 
+    pub fn if_not_exists(&mut self) -> &mut Self {
+        self.if_not_exists = true;
+        self
+    }
+
     pub fn add_field(&mut self, field: Field) -> &mut Self {
         if field.constraints.is_primary {
             self.primary_keys.push(field.name.as_str().to_string());
@@ -316,48 +347,65 @@ impl Table {
         self
     }
 
-    pub fn to_sql(&self, db_type: DatabaseType) -> CustomResult<String> {
-        let mut fields_sql: CustomResult<Vec<String>> =
-            self.fields.iter().map(|f| f.to_sql(db_type)).collect();
-        let fields_sql = fields_sql?;
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name.as_str() == name)
+    }
 
-        let mut sql = String::new();
-        
-        match db_type {
-            DatabaseType::SQLite => {
-                if self.primary_keys.len() > 1 {
-                    sql = format!(
-                        "CREATE TABLE {} (\n    {},\n    CONSTRAINT pk_{} PRIMARY KEY ({}))",
-                        self.name.as_str(),
-                        fields_sql.join(",\n    "),
-                        self.name.as_str(),
-                        self.primary_keys.join(", ")
-                    );
-                } else {
-                    sql = format!(
-                        "CREATE TABLE {} (\n    {}\n)",
-                        self.name.as_str(),
-                        fields_sql.join(",\n    ")
-                    );
-                }
-            }
-            _ => {
-                if self.primary_keys.len() > 1 {
-                    sql = format!(
-                        "CREATE TABLE {} (\n    {},\n    PRIMARY KEY ({}))",
-                        self.name.as_str(),
-                        fields_sql.join(",\n    "),
-                        self.primary_keys.join(", ")
-                    );
+    pub fn field_mut(&mut self, name: &str) -> CustomResult<&mut Field> {
+        let table = self.name.as_str().to_string();
+        self.fields
+            .iter_mut()
+            .find(|field| field.name.as_str() == name)
+            .ok_or_else(|| format!("表{}中不存在字段{}", table, name).into_custom_error())
+    }
+
+    pub fn to_sql(&self, db_type: DatabaseType) -> CustomResult<String> {
+        let composite_key = self.primary_keys.len() > 1;
+        let fields_sql: CustomResult<Vec<String>> = self
+            .fields
+            .iter()
+            .map(|f| {
+                if composite_key && f.constraints.is_primary {
+                    let mut field = f.clone();
+                    field.constraints.is_primary = false;
+                    field.to_sql(db_type)
                 } else {
-                    sql = format!(
-                        "CREATE TABLE {} (\n    {}\n)",
-                        self.name.as_str(),
-                        fields_sql.join(",\n    ")
-                    );
+                    f.to_sql(db_type)
                 }
-            }
-        }
+            })
+            .collect();
+        let fields_sql = fields_sql?;
+
+        let create = if self.if_not_exists {
+            "CREATE TABLE IF NOT EXISTS"
+        } else {
+            "CREATE TABLE"
+        };
+
+        let mut sql = if composite_key {
+            let primary_key = match db_type {
+                DatabaseType::SQLite => format!(
+                    "CONSTRAINT pk_{} PRIMARY KEY ({})",
+                    self.name.as_str(),
+                    self.primary_keys.join(", ")
+                ),
+                _ => format!("PRIMARY KEY ({})", self.primary_keys.join(", ")),
+            };
+            format!(
+                "{} {} (\n    {},\n    {})",
+                create,
+                self.name.as_str(),
+                fields_sql.join(",\n    "),
+                primary_key
+            )
+        } else {
+            format!(
+                "{} {} (\n    {}\n)",
+                create,
+                self.name.as_str(),
+                fields_sql.join(",\n    ")
+            )
+        };
 
         sql.push(';');
 
@@ -371,6 +419,10 @@ impl Table {
 
         Ok(sql)
     }
+
+    pub fn drop_sql(&self) -> String {
+        format!("DROP TABLE {};", self.name.as_str())
+    }
 }
 
 impl Index {
@@ -385,7 +437,7 @@ impl Index {
         })
     }
 
-    fn to_sql(&self, table_name: &str, _db_type: DatabaseType) -> CustomResult<String> {
+    pub fn to_sql(&self, table_name: &str, _db_type: DatabaseType) -> CustomResult<String> {
         let unique = if self.is_unique { "UNIQUE " } else { "" };
         Ok(format!(
             "CREATE {}INDEX {} ON {} ({});",
@@ -401,8 +453,200 @@ impl Index {
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum AlterOperation {
+    AddColumn(Field),
+    DropColumn(Identifier),
+    // 按当前定义重建表，只对 SQLite 生效，用于修正旧版本建表语句遗漏的约束
+    Rebuild,
+}
+
+impl AlterOperation {
+    // SQLite 不支持原地执行的操作需要重建表
+    fn requires_rebuild(&self, db_type: DatabaseType) -> bool {
+        if db_type != DatabaseType::SQLite {
+            return false;
+        }
+        match self {
+            AlterOperation::AddColumn(field) => {
+                let constraints = &field.constraints;
+                let non_constant_default = matches!(
+                    &constraints.default_value,
+                    Some(SafeValue::Text(value, _)) if value.starts_with("CURRENT_")
+                );
+                constraints.is_primary
+                    || constraints.is_unique
+                    || constraints.foreign_key.is_some()
+                    || non_constant_default
+                    || (!constraints.is_nullable && constraints.default_value.is_none())
+            }
+            AlterOperation::DropColumn(_) | AlterOperation::Rebuild => true,
+        }
+    }
+
+    fn to_sql(&self, table: &Identifier, db_type: DatabaseType) -> CustomResult<Vec<String>> {
+        let table = table.as_str();
+        Ok(match self {
+            AlterOperation::AddColumn(field) => {
+                let mut column = field.clone();
+                // MySQL 会忽略列定义中的 REFERENCES，需要单独添加外键约束
+                let foreign_key = match db_type {
+                    DatabaseType::MySQL => column.constraints.foreign_key.take(),
+                    _ => None,
+                };
+                let mut statements = vec![format!(
+                    "ALTER TABLE {} ADD COLUMN {};",
+                    table,
+                    column.to_sql(db_type)?
+                )];
+                if let Some(fk) = foreign_key {
+                    statements.push(Self::foreign_key_sql(table, &field.name, &fk));
+                }
+                statements
+            }
+            AlterOperation::DropColumn(column) => vec![format!(
+                "ALTER TABLE {} DROP COLUMN {};",
+                table,
+                column.as_str()
+            )],
+            AlterOperation::Rebuild => Vec::new(),
+        })
+    }
+
+    fn foreign_key_sql(table: &str, column: &Identifier, fk: &ForeignKey) -> String {
+        format!(
+            "ALTER TABLE {} ADD CONSTRAINT fk_{}_{} FOREIGN KEY ({}){};",
+            table,
+            table,
+            column.as_str(),
+            column.as_str(),
+            fk.to_sql()
+        )
+    }
+
+    fn apply(&self, table: &mut Table) -> CustomResult<()> {
+        match self {
+            AlterOperation::AddColumn(field) => {
+                if table.field(field.name.as_str()).is_some() {
+                    return Err(format!("字段{}已存在", field.name.as_str()).into_custom_error());
+                }
+                table.add_field(field.clone());
+            }
+            AlterOperation::DropColumn(column) => {
+                table.field_mut(column.as_str())?;
+                table.fields.retain(|field| field.name != *column);
+                table.primary_keys.retain(|key| key != column.as_str());
+                table.indexes.retain(|index| !index.fields.contains(column));
+            }
+            AlterOperation::Rebuild => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlterTable {
+    pub name: Identifier,
+    pub operations: Vec<AlterOperation>,
+}
+
+impl AlterTable {
+    pub fn new(name: &str) -> CustomResult<Self> {
+        Ok(Self {
+            name: Identifier::new(name.to_string())?,
+            operations: Vec::new(),
+        })
+    }
+
+    pub fn add_column(&mut self, field: Field) -> &mut Self {
+        self.operations.push(AlterOperation::AddColumn(field));
+        self
+    }
+
+    pub fn drop_column(&mut self, column: &str) -> CustomResult<&mut Self> {
+        self.operations
+            .push(AlterOperation::DropColumn(Identifier::new(
+                column.to_string(),
+            )?));
+        Ok(self)
+    }
+
+    pub fn rebuild(&mut self) -> &mut Self {
+        self.operations.push(AlterOperation::Rebuild);
+        self
+    }
+
+    // 将变更应用到内存中的表结构
+    pub fn apply(&self, table: &mut Table) -> CustomResult<()> {
+        for operation in &self.operations {
+            operation.apply(table)?;
+        }
+        Ok(())
+    }
+
+    // current 为变更前的表结构，SQLite 重建表时需要完整定义
+    pub fn to_sql(&self, db_type: DatabaseType, current: &Table) -> CustomResult<String> {
+        if self
+            .operations
+            .iter()
+            .any(|operation| operation.requires_rebuild(db_type))
+        {
+            return self.rebuild_sql(db_type, current);
+        }
+
+        let statements = self
+            .operations
+            .iter()
+            .map(|operation| operation.to_sql(&self.name, db_type))
+            .collect::<CustomResult<Vec<_>>>()?;
+        Ok(statements.concat().join("\n"))
+    }
+
+    // 创建新表、复制数据、删除旧表、重命名并重建索引
+    fn rebuild_sql(&self, db_type: DatabaseType, current: &Table) -> CustomResult<String> {
+        let mut rebuilt = current.clone();
+        self.apply(&mut rebuilt)?;
+
+        let mut temp = rebuilt.clone();
+        temp.name = Identifier::new(format!("{}__new", self.name.as_str()))?;
+        temp.indexes.clear();
+        temp.if_not_exists = false;
+
+        // 新增的列在旧表中没有数据，不参与复制
+        let columns = rebuilt
+            .fields
+            .iter()
+            .filter(|field| current.field(field.name.as_str()).is_some())
+            .map(|field| field.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut statements = vec![
+            temp.to_sql(db_type)?,
+            format!(
+                "INSERT INTO {} ({}) SELECT {} FROM {};",
+                temp.name.as_str(),
+                columns,
+                columns,
+                self.name.as_str()
+            ),
+            format!("DROP TABLE {};", self.name.as_str()),
+            format!(
+                "ALTER TABLE {} RENAME TO {};",
+                temp.name.as_str(),
+                self.name.as_str()
+            ),
+        ];
+        for index in &rebuilt.indexes {
+            statements.push(index.to_sql(self.name.as_str(), db_type)?);
+        }
+
+        Ok(statements.join("\n"))
+    }
+}
+
 // Schema构建器
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct SchemaBuilder {
     tables: Vec<Table>,
 }
@@ -417,17 +661,29 @@ impl SchemaBuilder {
         Ok(self)
     }
 
-    pub fn build(&self, db_type: DatabaseType) -> CustomResult<String> {
-        let mut sql = String::new();
-        for table in &self.tables {
-            sql.push_str(&table.to_sql(db_type)?);
-            sql.push_str("\n\n");
-        }
-        Ok(sql)
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    pub fn table_mut(&mut self, name: &str) -> CustomResult<&mut Table> {
+        self.tables
+            .iter_mut()
+            .find(|table| table.name.as_str() == name)
+            .ok_or_else(|| format!("表{}不存在", name).into_custom_error())
+    }
+
+    pub fn remove_table(&mut self, name: &str) -> CustomResult<Table> {
+        let position = self
+            .tables
+            .iter()
+            .position(|table| table.name.as_str() == name)
+            .ok_or_else(|| format!("表{}不存在", name).into_custom_error())?;
+        Ok(self.tables.remove(position))
     }
 }
 
-pub fn generate_schema(db_type: DatabaseType, db_prefix: SafeValue) -> CustomResult<String> {
+// 初始表结构，作为第一个迁移版本
+pub fn generate_schema(db_prefix: SafeValue) -> CustomResult<SchemaBuilder> {
     let db_prefix = db_prefix.to_string()?;
     let mut schema = SchemaBuilder::new();
 
@@ -744,5 +1000,475 @@ pub fn generate_schema(db_type: DatabaseType, db_prefix: SafeValue) -> CustomRes
 
     schema.add_table(post_taxonomies_table)?;
 
-    schema.build(db_type)
+    Ok(schema)
+}
+
+// API 密钥表，时间字段使用 Unix 秒，便于在各数据库间直接比较
+pub fn api_keys_table(db_prefix: &str) -> CustomResult<Table> {
+    let mut api_keys_table = Table::new(&format!("{}api_keys", db_prefix))?;
+    api_keys_table
+        .add_field(Field::new(
+            "id",
+            FieldType::VarChar(32),
+            FieldConstraint::new().primary(),
+        )?)
+        .add_field(Field::new(
+            "name",
+            FieldType::VarChar(100),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "key_hash",
+            FieldType::VarChar(64),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "scopes",
+            FieldType::Text,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "created_by",
+            FieldType::VarChar(100),
+            FieldConstraint::new(),
+        )?)
+        .add_field(Field::new(
+            "created_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "last_used_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "expires_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "revoked_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?);
+
+    Ok(api_keys_table)
+}
+
+// 登录失败记录，subject 为 ip:<地址> 或 user:<用户名哈希>
+pub fn login_attempts_table(db_prefix: &str) -> CustomResult<Table> {
+    let mut login_attempts_table = Table::new(&format!("{}login_attempts", db_prefix))?;
+    login_attempts_table
+        .add_field(Field::new(
+            "subject",
+            FieldType::VarChar(100),
+            FieldConstraint::new().primary(),
+        )?)
+        .add_field(Field::new(
+            "failures",
+            FieldType::Integer(false),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "last_failure_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "locked_until",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?);
+
+    Ok(login_attempts_table)
+}
+
+// 两步验证，confirmed_at 为 0 表示尚未完成绑定，recovery_codes 为空格分隔的恢复码哈希
+pub fn user_totp_table(db_prefix: &str) -> CustomResult<Table> {
+    let mut user_totp_table = Table::new(&format!("{}user_totp", db_prefix))?;
+    user_totp_table
+        .add_field(Field::new(
+            "username",
+            FieldType::VarChar(100),
+            FieldConstraint::new()
+                .primary()
+                .foreign_key(format!("{}users", db_prefix), "username".to_string())
+                .on_delete(ForeignKeyAction::Cascade)
+                .on_update(ForeignKeyAction::Cascade),
+        )?)
+        .add_field(Field::new(
+            "secret",
+            FieldType::VarChar(64),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "confirmed_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "last_step",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "recovery_codes",
+            FieldType::Text,
+            FieldConstraint::new().not_null(),
+        )?);
+
+    Ok(user_totp_table)
+}
+
+// 邮件中发送的一次性令牌，只存储哈希，purpose 区分找回密码和验证邮箱，used_at 为 0 表示未使用
+pub fn user_tokens_table(db_prefix: &str) -> CustomResult<Table> {
+    let mut user_tokens_table = Table::new(&format!("{}user_tokens", db_prefix))?;
+    user_tokens_table
+        .add_field(Field::new(
+            "token_hash",
+            FieldType::VarChar(64),
+            FieldConstraint::new().primary(),
+        )?)
+        .add_field(Field::new(
+            "username",
+            FieldType::VarChar(100),
+            FieldConstraint::new()
+                .not_null()
+                .foreign_key(format!("{}users", db_prefix), "username".to_string())
+                .on_delete(ForeignKeyAction::Cascade)
+                .on_update(ForeignKeyAction::Cascade),
+        )?)
+        .add_field(Field::new(
+            "purpose",
+            FieldType::VarChar(20),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "created_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "expires_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "used_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?);
+
+    Ok(user_tokens_table)
+}
+
+// 邮箱验证时间，0 表示未验证
+pub fn email_verified_field() -> CustomResult<Field> {
+    Field::new(
+        "email_verified_at",
+        FieldType::BigInt,
+        FieldConstraint::new()
+            .not_null()
+            .default(SafeValue::Integer(0)),
+    )
+}
+
+// 外部身份与本地用户的关联，同一提供方的 subject 只能关联一个用户
+pub fn user_identities_table(db_prefix: &str) -> CustomResult<Table> {
+    let mut user_identities_table = Table::new(&format!("{}user_identities", db_prefix))?;
+    user_identities_table
+        .add_field(Field::new(
+            "provider",
+            FieldType::VarChar(50),
+            FieldConstraint::new().primary(),
+        )?)
+        .add_field(Field::new(
+            "subject",
+            FieldType::VarChar(255),
+            FieldConstraint::new().primary(),
+        )?)
+        .add_field(Field::new(
+            "username",
+            FieldType::VarChar(100),
+            FieldConstraint::new()
+                .not_null()
+                .foreign_key(format!("{}users", db_prefix), "username".to_string())
+                .on_delete(ForeignKeyAction::Cascade)
+                .on_update(ForeignKeyAction::Cascade),
+        )?)
+        .add_field(Field::new(
+            "created_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?);
+
+    Ok(user_identities_table)
+}
+
+// 进行中的授权请求，state 只存储哈希，link_username 为空表示登录，否则为关联到该用户
+pub fn oidc_states_table(db_prefix: &str) -> CustomResult<Table> {
+    let mut oidc_states_table = Table::new(&format!("{}oidc_states", db_prefix))?;
+    oidc_states_table
+        .add_field(Field::new(
+            "state_hash",
+            FieldType::VarChar(64),
+            FieldConstraint::new().primary(),
+        )?)
+        .add_field(Field::new(
+            "provider",
+            FieldType::VarChar(50),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "verifier",
+            FieldType::VarChar(128),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "nonce",
+            FieldType::VarChar(64),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "link_username",
+            FieldType::VarChar(100),
+            FieldConstraint::new().not_null(),
+        )?)
+        .add_field(Field::new(
+            "expires_at",
+            FieldType::BigInt,
+            FieldConstraint::new().not_null(),
+        )?);
+
+    Ok(oidc_states_table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sql::memory_database;
+
+    const ALL: [DatabaseType; 3] = [
+        DatabaseType::PostgreSQL,
+        DatabaseType::MySQL,
+        DatabaseType::SQLite,
+    ];
+
+    fn posts() -> Table {
+        let mut table = Table::new("posts").unwrap();
+        table
+            .add_field(
+                Field::new(
+                    "id",
+                    FieldType::Integer(true),
+                    FieldConstraint::new().primary(),
+                )
+                .unwrap(),
+            )
+            .add_field(
+                Field::new(
+                    "title",
+                    FieldType::VarChar(100),
+                    FieldConstraint::new().not_null(),
+                )
+                .unwrap(),
+            )
+            .add_field(
+                Field::new("author", FieldType::VarChar(100), FieldConstraint::new()).unwrap(),
+            )
+            .add_index(Index::new("idx_posts_title", vec!["title".to_string()], false).unwrap());
+        table
+    }
+
+    fn author_key() -> FieldConstraint {
+        FieldConstraint::new()
+            .foreign_key("users".to_string(), "username".to_string())
+            .on_delete(ForeignKeyAction::Cascade)
+    }
+
+    fn alter() -> AlterTable {
+        AlterTable::new("posts").unwrap()
+    }
+
+    fn is_rebuild(sql: &str) -> bool {
+        sql.starts_with("CREATE TABLE posts__new")
+    }
+
+    #[test]
+    fn add_column_alters_in_place() {
+        let mut change = alter();
+        change.add_column(Field::new("summary", FieldType::Text, FieldConstraint::new()).unwrap());
+        for db_type in ALL {
+            assert_eq!(
+                change.to_sql(db_type, &posts()).unwrap(),
+                "ALTER TABLE posts ADD COLUMN summary TEXT;"
+            );
+        }
+    }
+
+    #[test]
+    fn add_column_with_foreign_key() {
+        let mut change = alter();
+        change.add_column(Field::new("editor", FieldType::VarChar(100), author_key()).unwrap());
+
+        assert_eq!(
+            change.to_sql(DatabaseType::PostgreSQL, &posts()).unwrap(),
+            "ALTER TABLE posts ADD COLUMN editor VARCHAR(100) REFERENCES users(username) ON DELETE CASCADE;"
+        );
+        assert_eq!(
+            change.to_sql(DatabaseType::MySQL, &posts()).unwrap(),
+            "ALTER TABLE posts ADD COLUMN editor VARCHAR(100);\n\
+             ALTER TABLE posts ADD CONSTRAINT fk_posts_editor FOREIGN KEY (editor) REFERENCES users(username) ON DELETE CASCADE;"
+        );
+        assert!(is_rebuild(
+            &change.to_sql(DatabaseType::SQLite, &posts()).unwrap()
+        ));
+    }
+
+    #[test]
+    fn add_required_column_rebuilds_sqlite() {
+        let mut change = alter();
+        change.add_column(
+            Field::new(
+                "slug",
+                FieldType::VarChar(100),
+                FieldConstraint::new().not_null(),
+            )
+            .unwrap(),
+        );
+        for db_type in [DatabaseType::PostgreSQL, DatabaseType::MySQL] {
+            assert_eq!(
+                change.to_sql(db_type, &posts()).unwrap(),
+                "ALTER TABLE posts ADD COLUMN slug VARCHAR(100) NOT NULL;"
+            );
+        }
+        let sql = change.to_sql(DatabaseType::SQLite, &posts()).unwrap();
+        assert!(is_rebuild(&sql));
+        // 新增的列在旧表中没有数据，不参与复制
+        assert!(sql.contains(
+            "INSERT INTO posts__new (id, title, author) SELECT id, title, author FROM posts;"
+        ));
+    }
+
+    #[test]
+    fn drop_column() {
+        let mut change = alter();
+        change.drop_column("author").unwrap();
+        for db_type in [DatabaseType::PostgreSQL, DatabaseType::MySQL] {
+            assert_eq!(
+                change.to_sql(db_type, &posts()).unwrap(),
+                "ALTER TABLE posts DROP COLUMN author;"
+            );
+        }
+        let sql = change.to_sql(DatabaseType::SQLite, &posts()).unwrap();
+        assert!(is_rebuild(&sql));
+        assert!(!sql.contains("author"));
+        assert!(sql.ends_with(
+            "DROP TABLE posts;\n\
+             ALTER TABLE posts__new RENAME TO posts;\n\
+             CREATE INDEX idx_posts_title ON posts (title);"
+        ));
+    }
+
+    #[test]
+    fn rebuild_only_applies_to_sqlite() {
+        let mut change = alter();
+        change.rebuild();
+        for db_type in [DatabaseType::PostgreSQL, DatabaseType::MySQL] {
+            assert_eq!(change.to_sql(db_type, &posts()).unwrap(), "");
+        }
+        let sql = change.to_sql(DatabaseType::SQLite, &posts()).unwrap();
+        assert!(is_rebuild(&sql));
+        assert!(sql.contains(
+            "INSERT INTO posts__new (id, title, author) SELECT id, title, author FROM posts;"
+        ));
+    }
+
+    #[tokio::test]
+    async fn sqlite_rebuild_keeps_rows() {
+        let sql = memory_database().await;
+        let db = sql.get_db();
+        let mut users = Table::new("users").unwrap();
+        users.add_field(
+            Field::new(
+                "username",
+                FieldType::VarChar(100),
+                FieldConstraint::new().primary(),
+            )
+            .unwrap(),
+        );
+        db.execute_batch(&users.to_sql(DatabaseType::SQLite).unwrap())
+            .await
+            .unwrap();
+        db.execute_batch(&posts().to_sql(DatabaseType::SQLite).unwrap())
+            .await
+            .unwrap();
+        db.execute_batch(
+            "INSERT INTO users (username) VALUES ('alice');\n\
+             INSERT INTO posts (title, author) VALUES ('first', 'alice');\n\
+             INSERT INTO posts (title, author) VALUES ('second', 'alice');",
+        )
+        .await
+        .unwrap();
+
+        // 数据库中的表没有外键，按带外键的定义重建并新增一列
+        let mut current = posts();
+        current.field_mut("author").unwrap().constraints = author_key();
+        let mut change = alter();
+        change.rebuild().add_column(
+            Field::new(
+                "views",
+                FieldType::Integer(false),
+                FieldConstraint::new()
+                    .not_null()
+                    .default(SafeValue::Integer(0)),
+            )
+            .unwrap(),
+        );
+        let rebuild = change.to_sql(DatabaseType::SQLite, &current).unwrap();
+        assert!(is_rebuild(&rebuild));
+        db.execute_batch(&rebuild).await.unwrap();
+
+        let rows = db
+            .execute_raw(
+                "SELECT id, title, author, views FROM posts ORDER BY id",
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["id"], 1);
+        assert_eq!(rows[0]["title"], "first");
+        assert_eq!(rows[1]["title"], "second");
+        assert_eq!(rows[1]["author"], "alice");
+        assert_eq!(rows[1]["views"], 0);
+
+        let indexes = db
+            .execute_raw(
+                "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'posts'",
+                vec![],
+            )
+            .await
+            .unwrap();
+        let names = indexes
+            .iter()
+            .map(|row| row["name"].as_str().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["idx_posts_title"]);
+
+        // 重建后的外键生效
+        assert!(db
+            .execute_batch("INSERT INTO posts (title, author) VALUES ('third', 'bob');")
+            .await
+            .is_err());
+        db.execute_raw("DELETE FROM users WHERE username = 'alice'", vec![])
+            .await
+            .unwrap();
+        let rows = db
+            .execute_raw("SELECT id FROM posts", vec![])
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+    }
 }
@@ -1,47 +1,126 @@
 use super::{
     builder::{self, SafeValue},
-    schema, DatabaseTrait,
+    migration, DatabaseTrait, DatabaseType, ProbeReport,
 };
-use crate::common::error::{CustomErrorInto, CustomResult};
+use crate::common::error::{CustomError, CustomResult, ErrorKind};
 use crate::config;
 use async_trait::async_trait;
 use serde_json::Value;
-use sqlx::{Column, Executor, Row, SqlitePool, TypeInfo};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous};
+use sqlx::{Acquire, Column, Executor, Row, SqlitePool, TypeInfo};
 use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(Clone)]
 pub struct Sqlite {
     pool: SqlitePool,
 }
 
+impl Sqlite {
+    // 内存数据库返回 None
+    fn db_file(db_config: &config::SqlConfig) -> CustomResult<Option<PathBuf>> {
+        match db_config.sqlite.path.as_str() {
+            ":memory:" => Ok(None),
+            "" => Ok(Some(
+                env::current_dir()?
+                    .join("assets")
+                    .join("sqllite")
+                    .join(&db_config.db_name),
+            )),
+            path => {
+                let path = PathBuf::from(path);
+                if !path.is_absolute() {
+                    return Err(CustomError::localized(
+                        ErrorKind::Internal,
+                        "database.sqlite_relative_path",
+                    ));
+                }
+                Ok(Some(path))
+            }
+        }
+    }
+
+    // 权限位无法反映 ACL、只读挂载等情况，实际创建并删除一个临时文件
+    fn dir_writable(dir: &Path) -> bool {
+        let probe = dir.join(format!(".echoes-probe-{}", std::process::id()));
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+        {
+            Ok(_) => std::fs::remove_file(&probe).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+fn bind_values(
+    query: &str,
+    values: Vec<SafeValue>,
+) -> sqlx::query::Query<'_, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'_>> {
+    let mut sqlx_query = sqlx::query(query);
+    for value in values {
+        match value {
+            SafeValue::Null => sqlx_query = sqlx_query.bind(None::<String>),
+            SafeValue::Bool(b) => sqlx_query = sqlx_query.bind(b),
+            SafeValue::Integer(i) => sqlx_query = sqlx_query.bind(i),
+            SafeValue::Float(f) => sqlx_query = sqlx_query.bind(f),
+            SafeValue::Text(s, _) => sqlx_query = sqlx_query.bind(s),
+            SafeValue::DateTime(dt) => sqlx_query = sqlx_query.bind(dt.to_rfc3339()),
+        }
+    }
+    sqlx_query
+}
+
 #[async_trait]
 impl DatabaseTrait for Sqlite {
     async fn connect(db_config: &config::SqlConfig, _db: bool) -> CustomResult<Self> {
-        let db_file = env::current_dir()?
-            .join("assets")
-            .join("sqllite")
-            .join(&db_config.db_name);
+        let options = match Self::db_file(db_config)? {
+            Some(db_file) => {
+                if !db_file.exists() {
+                    return Err(CustomError::localized(
+                        ErrorKind::Internal,
+                        "database.sqlite_missing",
+                    ));
+                }
+                SqliteConnectOptions::new().filename(db_file)
+            }
+            None => SqliteConnectOptions::from_str("sqlite::memory:")?,
+        };
 
-        if !db_file.exists() {
-            return Err("SQLite数据库文件不存在".into_custom_error());
+        let mut options = options
+            .journal_mode(SqliteJournalMode::from_str(&db_config.sqlite.journal_mode)?)
+            .synchronous(SqliteSynchronous::from_str(&db_config.sqlite.synchronous)?)
+            .busy_timeout(std::time::Duration::from_secs(db_config.sqlite.busy_timeout))
+            .foreign_keys(db_config.sqlite.foreign_keys);
+        for (key, value) in &db_config.options {
+            options = options.pragma(key.clone(), value.clone());
         }
 
-        let path = db_file
-            .to_str()
-            .ok_or("无法获取SQLite路径".into_custom_error())?;
-        let connection_str = format!("sqlite:///{}", path);
+        // 内存数据库在最后一个连接关闭时销毁，需要常驻一个连接
+        let mut pool_options = super::pool_options::<sqlx::Sqlite>(db_config);
+        if Self::db_file(db_config)?.is_none() {
+            pool_options = pool_options
+                .min_connections(db_config.min_connections.max(1))
+                .idle_timeout(None)
+                .max_lifetime(None);
+        }
 
         let pool = tokio::time::timeout(
-            std::time::Duration::from_secs(5),
-            SqlitePool::connect(&connection_str),
+            std::time::Duration::from_secs(db_config.acquire_timeout),
+            pool_options.connect_with(options),
         )
         .await
-        .map_err(|_| "连接超时".into_custom_error())??;
+        .map_err(|_| CustomError::localized(ErrorKind::Internal, "database.connect_timeout"))??;
 
         if let Err(e) = pool.acquire().await {
             pool.close().await;
-            return Err(format!("数据库连接测试失败: {}", e).into_custom_error());
+            return Err(
+                CustomError::localized(ErrorKind::Internal, "database.connect_failed")
+                    .with_arg("error", e),
+            );
         }
 
         Ok(Sqlite { pool })
@@ -52,19 +131,16 @@ impl DatabaseTrait for Sqlite {
         builder: &builder::QueryBuilder,
     ) -> CustomResult<Vec<HashMap<String, Value>>> {
         let (query, values) = builder.build()?;
+        self.execute_raw(&query, values).await
+    }
 
-        let mut sqlx_query = sqlx::query(&query);
+    async fn execute_raw(
+        &self,
+        query: &str,
+        values: Vec<SafeValue>,
+    ) -> CustomResult<Vec<HashMap<String, Value>>> {
 
-        for value in values {
-            match value {
-                SafeValue::Null => sqlx_query = sqlx_query.bind(None::<String>),
-                SafeValue::Bool(b) => sqlx_query = sqlx_query.bind(b),
-                SafeValue::Integer(i) => sqlx_query = sqlx_query.bind(i),
-                SafeValue::Float(f) => sqlx_query = sqlx_query.bind(f),
-                SafeValue::Text(s, _) => sqlx_query = sqlx_query.bind(s),
-                SafeValue::DateTime(dt) => sqlx_query = sqlx_query.bind(dt.to_rfc3339()),
-            }
-        }
+        let sqlx_query = bind_values(query, values);
 
         let rows = sqlx_query.fetch_all(&self.pool).await?;
 
@@ -94,27 +170,116 @@ impl DatabaseTrait for Sqlite {
             .collect())
     }
 
-    async fn initialization(db_config: config::SqlConfig) -> CustomResult<()> {
+    async fn execute_affected(&self, builder: &builder::QueryBuilder) -> CustomResult<u64> {
+        let (query, values) = builder.build()?;
+        Ok(bind_values(&query, values)
+            .execute(&self.pool)
+            .await?
+            .rows_affected())
+    }
+
+    // 重建表时会删除被引用的旧表，需要在事务外关闭外键约束，原本启用约束时提交前做完整性检查
+    async fn execute_batch(&self, sql: &str) -> CustomResult<()> {
+        let mut conn = self.pool.acquire().await?;
+        let foreign_keys: bool = sqlx::query_scalar("PRAGMA foreign_keys")
+            .fetch_one(&mut *conn)
+            .await?;
+        conn.execute("PRAGMA foreign_keys = OFF").await?;
+
+        let result = async {
+            let mut tx = conn.begin().await?;
+            tx.execute(sql).await?;
+            if foreign_keys
+                && !sqlx::query("PRAGMA foreign_key_check")
+                    .fetch_all(&mut *tx)
+                    .await?
+                    .is_empty()
+            {
+                tx.rollback().await?;
+                return Err(CustomError::localized(
+                    ErrorKind::Internal,
+                    "database.foreign_key_check_failed",
+                ));
+            }
+            tx.commit().await?;
+            Ok(())
+        }
+        .await;
+
+        if foreign_keys {
+            conn.execute("PRAGMA foreign_keys = ON").await?;
+        }
+        result
+    }
+
+    async fn initialization(db_config: config::SqlConfig, overwrite: bool) -> CustomResult<()> {
         let db_prefix = SafeValue::Text(
             format!("{}", db_config.db_prefix),
             builder::ValidationLevel::Strict,
         );
 
-        let sqlite_dir = env::current_dir()?.join("assets").join("sqllite");
-        std::fs::create_dir_all(&sqlite_dir)?;
-
-        let db_file = sqlite_dir.join(&db_config.db_name);
-        std::fs::File::create(&db_file)?;
+        // 已存在的数据库文件不再截断，是否覆盖由迁移器根据 overwrite 判断
+        let created = match Self::db_file(&db_config)? {
+            Some(db_file) if !db_file.exists() => {
+                if let Some(sqlite_dir) = db_file.parent() {
+                    std::fs::create_dir_all(sqlite_dir)?;
+                }
+                std::fs::File::create(&db_file)?;
+                Some(db_file)
+            }
+            _ => None,
+        };
 
-        let grammar = schema::generate_schema(super::DatabaseType::SQLite, db_prefix)?;
+        let result = async {
+            let db = Self::connect(&db_config, false).await?;
+            let result = migration::Migrator::new(&db, DatabaseType::SQLite, &db_prefix.to_string()?)
+                .install(overwrite)
+                .await;
+            db.close().await?;
+            result
+        }
+        .await;
 
-        let pool = Self::connect(&db_config, false).await?.pool;
+        // 本次新建的数据库文件在失败时删除
+        if let (Err(_), Some(db_file)) = (&result, created) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = db_file.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        result.map(|_| ())
+    }
 
-        pool.execute(grammar.as_str()).await?;
+    async fn probe(db_config: &config::SqlConfig) -> CustomResult<ProbeReport> {
+        let pool = SqlitePool::connect("sqlite::memory:").await?;
+        let version: String = sqlx::query_scalar("SELECT sqlite_version()")
+            .fetch_one(&pool)
+            .await?;
         pool.close().await;
 
-        Ok(())
+        let (database_exists, writable) = match Self::db_file(db_config)? {
+            None => (false, true),
+            Some(db_file) => {
+                // 目录可能还未创建，检查最近一级已存在的目录是否可写
+                let writable = db_file
+                    .ancestors()
+                    .skip(1)
+                    .find(|dir| dir.exists())
+                    .is_some_and(Self::dir_writable);
+                (db_file.exists(), writable)
+            }
+        };
+
+        Ok(ProbeReport {
+            server_version: format!("SQLite {}", version),
+            database_exists,
+            can_create_database: writable,
+            can_create_table: writable,
+            ..Default::default()
+        })
     }
+
     async fn close(&self) -> CustomResult<()> {
         self.pool.close().await;
         while !self.pool.is_closed() {
@@ -123,3 +288,76 @@ impl DatabaseTrait for Sqlite {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sql::builder::ValidationLevel;
+    use crate::storage::sql::memory_database;
+
+    #[tokio::test]
+    async fn probe_creates_a_file_to_check_the_directory() {
+        let dir = env::temp_dir().join(format!("echoes-probe-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut database = config::SqlConfig::default();
+
+        // 数据库所在目录还不存在时检查最近一级已存在的目录，探测文件不会留下
+        database.sqlite.path = dir.join("data").join("echoes.db").display().to_string();
+        let report = Sqlite::probe(&database).await.unwrap();
+        assert!(report.can_create_database && !report.database_exists);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // 最近一级已存在的路径是普通文件，无法在其中创建
+        std::fs::write(dir.join("file"), "").unwrap();
+        database.sqlite.path = dir.join("file").join("echoes.db").display().to_string();
+        let report = Sqlite::probe(&database).await.unwrap();
+        assert!(!report.can_create_database && !report.can_create_table);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrated_schema_enforces_foreign_keys() {
+        let sql = memory_database().await;
+        sql.migrate(false).await.unwrap();
+        let db = sql.get_db();
+        let users = sql.table_name("users");
+        let posts = sql.table_name("posts");
+        let insert_post = format!(
+            "INSERT INTO {} (author_name, title, content, status) VALUES (?, 'hello', 'world', 'draft')",
+            posts
+        );
+
+        let author = |name: &str| vec![SafeValue::Text(name.to_string(), ValidationLevel::Standard)];
+
+        let orphan = db
+            .execute_raw(&insert_post, author("ghost"))
+            .await
+            .unwrap_err();
+        assert!(orphan.to_string().contains("FOREIGN KEY"));
+
+        db.execute_raw(
+            &format!(
+                "INSERT INTO {} (username, email, password_hash, role) VALUES ('alice', 'alice@example.com', 'x', 'administrator')",
+                users
+            ),
+            vec![],
+        )
+        .await
+        .unwrap();
+        db.execute_raw(&insert_post, author("alice")).await.unwrap();
+
+        // ON DELETE CASCADE 只有在外键启用时才会生效
+        db.execute_raw(
+            &format!("DELETE FROM {} WHERE username = 'alice'", users),
+            vec![],
+        )
+        .await
+        .unwrap();
+        let rows = db
+            .execute_raw(&format!("SELECT id FROM {}", posts), vec![])
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+    }
+}
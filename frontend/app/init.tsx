@@ -146,10 +146,11 @@ const DatabaseConfig: React.FC<StepProps> = ({ onNext }) => {
     const getRequiredFields = () => {
       switch (dbType) {
         case "sqllite":
-          return ["db_prefix", "db_name"];
+          return ["install_token", "db_prefix", "db_name"];
         case "postgresql":
         case "mysql":
           return [
+            "install_token",
             "db_host",
             "db_prefix",
             "db_port",
@@ -177,6 +178,8 @@ const DatabaseConfig: React.FC<StepProps> = ({ onNext }) => {
     if (emptyFields.length > 0) {
       const fieldNames = emptyFields.map((field) => {
         switch (field) {
+          case "install_token":
+            return "安装令牌";
           case "db_host":
             return "数据库地址";
           case "db_prefix":
@@ -208,6 +211,7 @@ const DatabaseConfig: React.FC<StepProps> = ({ onNext }) => {
     setLoading(true);
     try {
       const formFields = getFormData([
+        "install_token",
         "db_host",
         "db_prefix",
         "db_port",
@@ -226,6 +230,7 @@ const DatabaseConfig: React.FC<StepProps> = ({ onNext }) => {
         db_name: formFields?.db_name ?? "",
       };
 
+      localStorage.setItem("install_token", formFields.install_token);
       await http.post("/sql", formData);
 
       toast.success("数据库配置成功！");
@@ -269,6 +274,13 @@ const DatabaseConfig: React.FC<StepProps> = ({ onNext }) => {
           </Select.Root>
         </Box>
 
+        <InputField
+          label="安装令牌"
+          name="install_token"
+          hint="启动服务时输出在控制台，也保存在 assets/install_token"
+          required
+        />
+
         {dbType === "postgresql" && (
           <>
             <InputField
@@ -384,8 +396,7 @@ const DatabaseConfig: React.FC<StepProps> = ({ onNext }) => {
 
 interface InstallReplyData {
   token: string;
-  username: string;
-  password: string;
+  api_key: string;
 }
 
 const AdminConfig: React.FC<StepProps> = ({ onNext }) => {
@@ -415,13 +426,13 @@ const AdminConfig: React.FC<StepProps> = ({ onNext }) => {
       };
 
       const response = (await http.post("/administrator", requestData)) as InstallReplyData;
-      const { token, username, password } = response;
+      const { token, api_key } = response;
 
       localStorage.setItem("token", token);
+      localStorage.removeItem("install_token");
 
       await updateEnvConfig({
-        VITE_API_USERNAME: username,
-        VITE_API_PASSWORD: password,
+        VITE_API_KEY: api_key,
       });
 
       toast.success("管理员账号创建成功！");
@@ -462,7 +473,7 @@ const SetupComplete: React.FC = () => {
         <Text size="5" weight="medium">
           恭喜！安装已完成
         </Text>
-        <Text size="3">系统正在重启中，请稍候...</Text>
+        <Text size="3">正在进入站点，请稍候...</Text>
         <Box mt="4">
           <Flex justify="center">
             <Box className="animate-spin rounded-full h-8 w-8 border-b-2 border-current"></Box>
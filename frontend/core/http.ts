@@ -39,6 +39,13 @@ export class HttpClient {
       }
     }
 
+    if (typeof window !== 'undefined' && !headers.has("X-Install-Token")) {
+      const installToken = localStorage.getItem("install_token");
+      if (installToken) {
+        headers.set("X-Install-Token", installToken);
+      }
+    }
+
     return { ...options, headers };
   }
 
@@ -235,16 +242,4 @@ export class HttpClient {
   ): Promise<T> {
     return this.api<T>(endpoint, { ...options, method: "DELETE" });
   }
-
-  public async systemToken<T>(): Promise<T> {
-    const formData = {
-      username: import.meta.env.VITE_API_USERNAME,
-      password: import.meta.env.VITE_API_PASSWORD,
-    };
-
-    return this.api<T>("/auth/token/system", {
-      method: "POST",
-      body: JSON.stringify(formData),
-    });
-  }
 }
@@ -99,9 +99,8 @@ export class ModuleManager {
       this.step = Number(step) || 0;
 
       if (this.step >= 3) {
-        const token = await http.systemToken<string>();
         const headers = {
-          Authorization: `Bearer ${token}`
+          Authorization: `Bearer ${import.meta.env.VITE_API_KEY}`
         };
         
         const field = await http.get("/field/system/0", { headers }) as Array<Field> | undefined;